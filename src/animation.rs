@@ -0,0 +1,83 @@
+use std::f32::consts::TAU;
+
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+
+/// How `RenderClock` seconds map to the `t` value fed to the expressions.
+/// The periodic terminals (`time`, `sin_t`, `saw`) all repeat when `t`
+/// advances by TAU, which takes `period` seconds
+#[derive(Resource, Debug, Clone)]
+pub struct AnimationTime {
+    pub period: f32,
+    /// Wrap `t` every period so the raw `t` terminal loops as well
+    pub looping: bool,
+}
+
+impl Default for AnimationTime {
+    // A TAU period keeps `time` equal to `sin(seconds)`
+    fn default() -> Self {
+        AnimationTime {
+            period: TAU,
+            looping: false,
+        }
+    }
+}
+
+impl AnimationTime {
+    fn scale(&self) -> f32 {
+        TAU / self.period
+    }
+
+    /// `t` at `seconds` of the `RenderClock`, for both renderers
+    pub fn at(&self, seconds: f32) -> f32 {
+        let t = seconds * self.scale();
+        match self.looping {
+            true => t.rem_euclid(TAU),
+            false => t,
+        }
+    }
+
+    /// Seconds at which `at` gives `t`, ignoring the wrap of `looping`
+    pub fn seconds(&self, t: f32) -> f32 {
+        t / self.scale()
+    }
+}
+
+/// Bounds of `AnimationTime::period` for `[` and `]`, past them `t` would
+/// end up infinite or NaN
+const MIN_PERIOD: f32 = TAU / 64.;
+const MAX_PERIOD: f32 = TAU * 1024.;
+
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AnimationTime>()
+            .add_systems(
+                Update,
+                slow_down.run_if(input_just_pressed(KeyCode::BracketLeft)),
+            )
+            .add_systems(
+                Update,
+                speed_up.run_if(input_just_pressed(KeyCode::BracketRight)),
+            )
+            .add_systems(
+                Update,
+                toggle_looping.run_if(input_just_pressed(KeyCode::KeyL)),
+            );
+    }
+}
+
+fn slow_down(mut animation: ResMut<AnimationTime>) {
+    animation.period = (animation.period * 2.).min(MAX_PERIOD);
+    info!("animation period: {}s", animation.period);
+}
+
+fn speed_up(mut animation: ResMut<AnimationTime>) {
+    animation.period = (animation.period / 2.).max(MIN_PERIOD);
+    info!("animation period: {}s", animation.period);
+}
+
+fn toggle_looping(mut animation: ResMut<AnimationTime>) {
+    animation.looping = !animation.looping;
+    info!("animation looping: {}", animation.looping);
+}
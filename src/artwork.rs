@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    func_gen::{generate_tree, NodeKind, NodeTernop, MAX_DEPTH},
+    grammar::Grammar,
+    optimize::simplify,
+    seed::Seed,
+};
+
+/// The r, g and b expressions drawn by both renderers
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct Artwork {
+    /// Seed the trees were generated from, kept for reference only
+    pub seed: Option<u64>,
+    pub r: NodeKind,
+    pub g: NodeKind,
+    pub b: NodeKind,
+}
+
+impl Artwork {
+    pub fn generate(grammar: &Grammar, seed: u64, depth: u32, channels: Channels) -> Self {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+
+        let [r, g, b] = match channels {
+            Channels::Independent => [(); 3].map(|_| generate_tree(grammar, depth, &mut rng)),
+            Channels::Shared => {
+                let base = generate_tree(grammar, depth, &mut rng);
+                [(); 3].map(|_| {
+                    let modulation = generate_tree(grammar, depth / 2, &mut rng);
+                    NodeKind::Mix(NodeTernop::new(
+                        Box::new(base.clone()),
+                        Box::new(modulation),
+                        Box::new(NodeKind::Random(rng.gen_range(0.2..0.6))),
+                    ))
+                })
+            }
+        };
+
+        Artwork {
+            seed: Some(seed),
+            r,
+            g,
+            b,
+        }
+    }
+
+    /// Artwork without a seed drawing the r, g and b `formulas`
+    pub fn from_formulas(formulas: [&str; 3]) -> Result<Self, String> {
+        let parse = |name: &str, formula: &str| {
+            formula
+                .parse::<NodeKind>()
+                .map_err(|error| format!("invalid {} formula, {}", name, error))
+        };
+        let [r, g, b] = formulas;
+        Ok(Artwork {
+            seed: None,
+            r: parse("r", r)?,
+            g: parse("g", g)?,
+            b: parse("b", b)?,
+        })
+    }
+
+    /// Same artwork with each tree simplified, it renders identically
+    pub fn simplified(&self) -> Self {
+        Artwork {
+            seed: self.seed,
+            r: simplify(&self.r),
+            g: simplify(&self.g),
+            b: simplify(&self.b),
+        }
+    }
+
+    /// Read a `.ron` or `.json` file written by `save`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("could not read {}: {}", path.display(), error))?;
+
+        let artwork = match Format::of(path)? {
+            Format::Ron => ron::from_str(&text).map_err(|error| error.to_string()),
+            Format::Json => serde_json::from_str(&text).map_err(|error| error.to_string()),
+        };
+        artwork.map_err(|error| format!("invalid artwork {}: {}", path.display(), error))
+    }
+
+    /// Write the trees as `.ron` or `.json`, picked from the extension
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = match Format::of(path)? {
+            Format::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|error| error.to_string())?,
+            Format::Json => {
+                serde_json::to_string_pretty(self).map_err(|error| error.to_string())?
+            }
+        };
+
+        std::fs::write(path, text)
+            .map_err(|error| format!("could not write {}: {}", path.display(), error))
+    }
+}
+
+/// How the r, g and b trees of a generated artwork relate to each other
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Channels {
+    /// Three unrelated trees
+    #[default]
+    Independent,
+    /// One base tree mixed with a smaller tree per channel, the colors stay
+    /// coherent and the base is only evaluated once per pixel
+    Shared,
+}
+
+enum Format {
+    Ron,
+    Json,
+}
+
+impl Format {
+    fn of(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => Ok(Format::Ron),
+            Some("json") => Ok(Format::Json),
+            _ => Err(format!("{} should end in .ron or .json", path.display())),
+        }
+    }
+}
+
+/// Whether the artwork follows the `Seed` and `Grammar`, or was set directly
+#[derive(Resource, Debug, Clone, Default, PartialEq, Eq)]
+pub enum ArtworkSource {
+    #[default]
+    Seed,
+    Loaded,
+}
+
+pub struct ArtworkPlugin {
+    /// Trees to show instead of the seed's until the seed changes
+    pub loaded: Option<Artwork>,
+}
+
+impl Plugin for ArtworkPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(artwork) = &self.loaded {
+            app.insert_resource(artwork.clone())
+                .insert_resource(ArtworkSource::Loaded);
+        }
+
+        app.init_resource::<ArtworkSource>()
+            .init_resource::<Channels>()
+            .add_systems(PreUpdate, update_artwork)
+            .add_systems(
+                Update,
+                dump_artwork.run_if(input_just_pressed(KeyCode::KeyD)),
+            )
+            .add_systems(
+                Update,
+                switch_channels.run_if(input_just_pressed(KeyCode::KeyM)),
+            );
+    }
+}
+
+// Regenerate the trees when the seed, grammar or channels change, a new seed
+// also replaces loaded trees
+fn update_artwork(
+    mut commands: Commands,
+    seed: Res<Seed>,
+    grammar: Res<Grammar>,
+    channels: Res<Channels>,
+    mut source: ResMut<ArtworkSource>,
+    artwork: Option<Res<Artwork>>,
+) {
+    if seed.is_changed() && !seed.is_added() && *source != ArtworkSource::Seed {
+        *source = ArtworkSource::Seed;
+    }
+
+    if *source == ArtworkSource::Seed
+        && (artwork.is_none() || seed.is_changed() || grammar.is_changed() || channels.is_changed())
+    {
+        info!("seed: {}", seed.0);
+        commands.insert_resource(Artwork::generate(&grammar, seed.0, MAX_DEPTH, *channels));
+    }
+}
+
+fn switch_channels(mut channels: ResMut<Channels>) {
+    *channels = match *channels {
+        Channels::Independent => Channels::Shared,
+        Channels::Shared => Channels::Independent,
+    };
+    info!("channels: {:?}", *channels);
+}
+
+fn dump_artwork(artwork: Res<Artwork>) {
+    let path = match artwork.seed {
+        Some(seed) => format!("artwork-{}.ron", seed),
+        None => "artwork.ron".to_string(),
+    };
+
+    match artwork.save(Path::new(&path)) {
+        Ok(()) => info!("artwork written to {}", path),
+        Err(error) => error!("{}", error),
+    }
+}
@@ -0,0 +1,270 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::{
+    artwork::{Artwork, Channels},
+    grammar::Grammar,
+    render::{generate_image, render_pixels, CpuBackend},
+    supersampling::Supersampling,
+};
+
+/// 3x5 bitmaps of the digits 0-9, one bit per pixel, row by row from the top
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+const DIGIT_WIDTH: u32 = 3;
+const DIGIT_HEIGHT: u32 = 5;
+const LABEL_SCALE: u32 = 2;
+const LABEL_HEIGHT: u32 = (DIGIT_HEIGHT + 2) * LABEL_SCALE;
+
+/// Settings for rendering many seeds as thumbnails on a single image
+#[derive(Debug)]
+pub struct ContactSheet {
+    pub seeds: Vec<u64>,
+    pub tile: u32,
+    pub columns: u32,
+}
+
+/// Most pixels a sheet can have, a GiB of RGBA
+pub const MAX_SHEET_PIXELS: u64 = 1 << 28;
+
+impl ContactSheet {
+    /// Fails when the sheet would have more than `MAX_SHEET_PIXELS`, before
+    /// anything is allocated
+    pub fn new(seeds: Vec<u64>, tile: u32, columns: u32) -> Result<Self, String> {
+        let count = seeds.len() as u64;
+        let width = count.min(columns as u64) * tile as u64;
+        let height = count.div_ceil(columns as u64) * (tile as u64 + LABEL_HEIGHT as u64);
+        if width
+            .checked_mul(height)
+            .is_none_or(|pixels| pixels > MAX_SHEET_PIXELS)
+        {
+            return Err(format!(
+                "a {}x{} sheet is over {} pixels, use a smaller `--tile` or fewer seeds",
+                width, height, MAX_SHEET_PIXELS
+            ));
+        }
+
+        Ok(ContactSheet {
+            seeds,
+            tile,
+            columns,
+        })
+    }
+
+    fn rows(&self) -> u32 {
+        (self.seeds.len() as u32).div_ceil(self.columns)
+    }
+
+    /// Top left corner of the tile showing the `index`th seed
+    fn tile_origin(&self, index: usize) -> UVec2 {
+        let index = index as u32;
+        UVec2::new(
+            index % self.columns * self.tile,
+            index / self.columns * (self.tile + LABEL_HEIGHT),
+        )
+    }
+
+    fn size(&self) -> UVec2 {
+        UVec2::new(
+            self.columns.min(self.seeds.len() as u32) * self.tile,
+            self.rows() * (self.tile + LABEL_HEIGHT),
+        )
+    }
+
+    pub fn render(
+        &self,
+        grammar: &Grammar,
+        depth: u32,
+        channels: Channels,
+        supersampling: Supersampling,
+        time: f32,
+    ) -> Image {
+        let size = self.size();
+        let mut sheet = generate_image(size.x, size.y);
+        let mut tile = generate_image(self.tile, self.tile);
+
+        for (index, seed) in self.seeds.iter().enumerate() {
+            render_pixels(
+                &mut tile,
+                &Artwork::generate(grammar, *seed, depth, channels),
+                time,
+                CpuBackend::default(),
+                supersampling,
+            );
+
+            // Offsets in `usize`, large sheets have more bytes than `u32` holds
+            let origin = self.tile_origin(index);
+            let (left, top) = (origin.x as usize, origin.y as usize);
+            let row_bytes = self.tile as usize * 4;
+            for y in 0..self.tile as usize {
+                let from = y * row_bytes;
+                let to = ((top + y) * size.x as usize + left) * 4;
+                sheet.data[to..to + row_bytes].copy_from_slice(&tile.data[from..from + row_bytes]);
+            }
+
+            draw_label(
+                &mut sheet,
+                origin + UVec2::new(0, self.tile),
+                self.tile,
+                *seed,
+            );
+        }
+
+        sheet
+    }
+
+    /// Page showing every seed of the sheet, cut out of `sheet_url` with CSS
+    pub fn html_index(&self, sheet_url: &str) -> String {
+        let tiles: String = self
+            .seeds
+            .iter()
+            .enumerate()
+            .map(|(index, seed)| {
+                let origin = self.tile_origin(index);
+                format!(
+                    "<figure><div class=\"art\" style=\"background-position: -{}px -{}px\"></div>\
+                     <figcaption>{}</figcaption></figure>\n",
+                    origin.x, origin.y, seed
+                )
+            })
+            .collect();
+
+        format!(
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>randomart seeds</title>
+<style>
+body {{ background: #262626; color: #e6e6e6; font-family: monospace; }}
+figure {{ display: inline-block; margin: 8px; text-align: center; }}
+.art {{ width: {tile}px; height: {tile}px; background-image: url(\"{url}\"); }}
+</style>
+</head>
+<body>
+{tiles}</body>
+</html>
+",
+            tile = self.tile,
+            url = sheet_url,
+            tiles = tiles
+        )
+    }
+}
+
+/// Write `seed` centered in the label strip below a tile
+fn draw_label(sheet: &mut Image, origin: UVec2, width: u32, seed: u64) {
+    let text = seed.to_string();
+    // Long seeds on small tiles fall back to unscaled digits
+    let scale = match text.len() as u32 * (DIGIT_WIDTH + 1) * LABEL_SCALE > width {
+        true => 1,
+        false => LABEL_SCALE,
+    };
+    let advance = (DIGIT_WIDTH + 1) * scale;
+    let text_width = text.len() as u32 * advance;
+    let mut x = origin.x + width.saturating_sub(text_width) / 2;
+    let y = origin.y + LABEL_SCALE;
+    let sheet_width = sheet.width();
+
+    for digit in text.bytes().map(|b| (b - b'0') as usize) {
+        for row in 0..DIGIT_HEIGHT {
+            for column in 0..DIGIT_WIDTH {
+                let bit = (DIGIT_HEIGHT - row) * DIGIT_WIDTH - column - 1;
+                if DIGITS[digit] >> bit & 1 == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = x + column * scale + dx;
+                        let py = y + row * scale + dy;
+                        if px < origin.x + width {
+                            let i = (py as usize * sheet_width as usize + px as usize) * 4;
+                            sheet.data[i..i + 4].copy_from_slice(&[255, 255, 255, 255]);
+                        }
+                    }
+                }
+            }
+        }
+        x += advance;
+    }
+}
+
+/// Most seeds a sheet can hold, a 100×100 grid
+pub const MAX_SEEDS: usize = 10_000;
+
+/// Parse `0..100`, `0..=99` or a comma separated list like `4,8,15`
+pub fn parse_seeds(text: &str) -> Result<Vec<u64>, String> {
+    let invalid = || format!("invalid seeds `{}`", text);
+    let number = |n: &str| n.trim().parse::<u64>().map_err(|_| invalid());
+
+    // One past the limit is enough to know it's exceeded
+    let seeds: Vec<u64> = if let Some((start, end)) = text.split_once("..=") {
+        (number(start)?..=number(end)?)
+            .take(MAX_SEEDS + 1)
+            .collect()
+    } else if let Some((start, end)) = text.split_once("..") {
+        (number(start)?..number(end)?).take(MAX_SEEDS + 1).collect()
+    } else {
+        text.split(',').map(number).collect::<Result<_, _>>()?
+    };
+
+    if seeds.is_empty() {
+        return Err(invalid());
+    }
+    if seeds.len() > MAX_SEEDS {
+        return Err(format!("`{}` has more than {} seeds", text, MAX_SEEDS));
+    }
+    Ok(seeds)
+}
+
+/// Url the index page uses for the sheet, relative when they share a folder
+pub fn sheet_url(sheet: &Path, index: &Path) -> String {
+    match (sheet.parent(), index.parent(), sheet.file_name()) {
+        (Some(a), Some(b), Some(name)) if a == b => name.to_string_lossy().into_owned(),
+        _ => sheet
+            .canonicalize()
+            .unwrap_or_else(|_| sheet.to_path_buf())
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_seeds() {
+        assert_eq!(parse_seeds("2..5").unwrap(), vec![2, 3, 4]);
+        assert_eq!(parse_seeds("2..=4").unwrap(), vec![2, 3, 4]);
+        assert_eq!(parse_seeds("4, 8,15").unwrap(), vec![4, 8, 15]);
+        assert!(parse_seeds("5..5").is_err());
+    }
+
+    #[test]
+    fn caps_seed_count() {
+        assert_eq!(parse_seeds("0..10000").unwrap().len(), MAX_SEEDS);
+        assert!(parse_seeds("0..10001").is_err());
+        assert!(parse_seeds("0..18446744073709551615").is_err());
+        assert!(parse_seeds("0..=18446744073709551615").is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_sheets() {
+        assert!(ContactSheet::new((0..100).collect(), 128, 8).is_ok());
+        let error = ContactSheet::new((0..10_000).collect(), 512, 8).unwrap_err();
+        assert!(error.starts_with("a 4096x657500 sheet"), "{}", error);
+        assert!(ContactSheet::new(vec![0, 1], u32::MAX, u32::MAX).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::func_gen::{safe_div, safe_log, safe_pow, safe_smoothstep, saw, NodeKind};
+
+/// One step of a `Program`, operations pop their operands off the stack and
+/// push their result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    X,
+    Y,
+    Constant(f32),
+    Add,
+    Mult,
+    Sqrt,
+    Abs,
+    Sin,
+    Mod,
+    Gt,
+    Time,
+    Sub,
+    Div,
+    Cos,
+    Tan,
+    Exp,
+    Log,
+    Pow,
+    Min,
+    Max,
+    Atan2,
+    Floor,
+    Fract,
+    Mix,
+    Smoothstep,
+    Clamp,
+    Lt,
+    Radius,
+    Angle,
+    DistTo(f32, f32),
+    T,
+    SinT(f32),
+    Saw,
+    /// Pop a condition and continue at the given index unless it's above 0
+    JumpUnlessPositive(usize),
+    Jump(usize),
+}
+
+impl Instruction {
+    fn of(node: &NodeKind) -> Self {
+        match node {
+            NodeKind::X => Instruction::X,
+            NodeKind::Y => Instruction::Y,
+            NodeKind::Random(r) => Instruction::Constant(*r),
+            NodeKind::Add(_) => Instruction::Add,
+            NodeKind::Mult(_) => Instruction::Mult,
+            NodeKind::Sqrt(_) => Instruction::Sqrt,
+            NodeKind::Abs(_) => Instruction::Abs,
+            NodeKind::Sin(_) => Instruction::Sin,
+            NodeKind::Mod(_) => Instruction::Mod,
+            NodeKind::Gt(_) => Instruction::Gt,
+            NodeKind::Time => Instruction::Time,
+            NodeKind::Sub(_) => Instruction::Sub,
+            NodeKind::Div(_) => Instruction::Div,
+            NodeKind::Cos(_) => Instruction::Cos,
+            NodeKind::Tan(_) => Instruction::Tan,
+            NodeKind::Exp(_) => Instruction::Exp,
+            NodeKind::Log(_) => Instruction::Log,
+            NodeKind::Pow(_) => Instruction::Pow,
+            NodeKind::Min(_) => Instruction::Min,
+            NodeKind::Max(_) => Instruction::Max,
+            NodeKind::Atan2(_) => Instruction::Atan2,
+            NodeKind::Floor(_) => Instruction::Floor,
+            NodeKind::Fract(_) => Instruction::Fract,
+            NodeKind::Mix(_) => Instruction::Mix,
+            NodeKind::Smoothstep(_) => Instruction::Smoothstep,
+            NodeKind::Clamp(_) => Instruction::Clamp,
+            NodeKind::Lt(_) => Instruction::Lt,
+            NodeKind::If(_) => unreachable!("`if` compiles to jumps"),
+            NodeKind::Radius => Instruction::Radius,
+            NodeKind::Angle => Instruction::Angle,
+            NodeKind::DistTo(px, py) => Instruction::DistTo(*px, *py),
+            NodeKind::T => Instruction::T,
+            NodeKind::SinT(freq) => Instruction::SinT(*freq),
+            NodeKind::Saw => Instruction::Saw,
+        }
+    }
+}
+
+/// A tree flattened into stack machine instructions, computes the same
+/// values as `eval` without walking the boxed nodes for every pixel
+#[derive(Debug, Clone)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    stack_size: usize,
+}
+
+impl Program {
+    pub fn compile(node: &NodeKind) -> Self {
+        let mut program = Program {
+            instructions: Vec::new(),
+            stack_size: 0,
+        };
+        program.emit(node, 0);
+        program
+    }
+
+    // `depth` is the number of values already on the stack
+    fn emit(&mut self, node: &NodeKind, depth: usize) {
+        // Only the taken branch is evaluated, like in `eval`
+        if let NodeKind::If(node_ternop) = node {
+            self.emit(&node_ternop.a, depth);
+            let jump_to_else = self.instructions.len();
+            self.instructions.push(Instruction::JumpUnlessPositive(0));
+            self.emit(&node_ternop.b, depth);
+            let jump_to_end = self.instructions.len();
+            self.instructions.push(Instruction::Jump(0));
+            self.instructions[jump_to_else] =
+                Instruction::JumpUnlessPositive(self.instructions.len());
+            self.emit(&node_ternop.c, depth);
+            self.instructions[jump_to_end] = Instruction::Jump(self.instructions.len());
+            return;
+        }
+
+        for (i, child) in node.children().into_iter().enumerate() {
+            self.emit(child, depth + i);
+        }
+        self.stack_size = self.stack_size.max(depth + 1);
+        self.instructions.push(Instruction::of(node));
+    }
+
+    /// Scratch space for `run`, reuse it across pixels
+    pub fn stack(&self) -> Vec<f32> {
+        vec![0.; self.stack_size]
+    }
+
+    pub fn run(&self, x: f32, y: f32, time: f32, stack: &mut [f32]) -> f32 {
+        let mut top = 0;
+        let mut next = 0;
+
+        while let Some(instruction) = self.instructions.get(next) {
+            next += 1;
+            let value = match *instruction {
+                Instruction::X => x,
+                Instruction::Y => y,
+                Instruction::Constant(r) => r,
+                Instruction::Add => binop(stack, &mut top, |a, b| a + b),
+                Instruction::Mult => binop(stack, &mut top, |a, b| a * b),
+                Instruction::Sqrt => unop(stack, &mut top, |v| v.abs().sqrt()),
+                Instruction::Abs => unop(stack, &mut top, f32::abs),
+                Instruction::Sin => unop(stack, &mut top, f32::sin),
+                Instruction::Mod => binop(stack, &mut top, |a, b| a % b),
+                Instruction::Gt => binop(stack, &mut top, |a, b| (a > b) as i32 as f32),
+                Instruction::Time => time.sin(),
+                Instruction::Sub => binop(stack, &mut top, |a, b| a - b),
+                Instruction::Div => binop(stack, &mut top, safe_div),
+                Instruction::Cos => unop(stack, &mut top, f32::cos),
+                Instruction::Tan => unop(stack, &mut top, f32::tan),
+                Instruction::Exp => unop(stack, &mut top, f32::exp),
+                Instruction::Log => unop(stack, &mut top, safe_log),
+                Instruction::Pow => binop(stack, &mut top, safe_pow),
+                Instruction::Min => binop(stack, &mut top, f32::min),
+                Instruction::Max => binop(stack, &mut top, f32::max),
+                Instruction::Atan2 => binop(stack, &mut top, f32::atan2),
+                Instruction::Floor => unop(stack, &mut top, f32::floor),
+                Instruction::Fract => unop(stack, &mut top, |v| v - v.floor()),
+                Instruction::Mix => ternop(stack, &mut top, |a, b, t| a * (1. - t) + b * t),
+                Instruction::Smoothstep => ternop(stack, &mut top, safe_smoothstep),
+                Instruction::Clamp => ternop(stack, &mut top, |v, low, high| v.max(low).min(high)),
+                Instruction::Lt => binop(stack, &mut top, |a, b| (a < b) as i32 as f32),
+                Instruction::Radius => (x * x + y * y).sqrt(),
+                Instruction::Angle => y.atan2(x),
+                Instruction::DistTo(px, py) => ((x - px) * (x - px) + (y - py) * (y - py)).sqrt(),
+                Instruction::T => time,
+                Instruction::SinT(freq) => (time * freq).sin(),
+                Instruction::Saw => saw(time),
+                Instruction::JumpUnlessPositive(target) => {
+                    top -= 1;
+                    if stack[top] <= 0. || stack[top].is_nan() {
+                        next = target;
+                    }
+                    continue;
+                }
+                Instruction::Jump(target) => {
+                    next = target;
+                    continue;
+                }
+            };
+            stack[top] = value;
+            top += 1;
+        }
+
+        stack[0]
+    }
+}
+
+#[inline(always)]
+fn unop(stack: &[f32], top: &mut usize, f: impl Fn(f32) -> f32) -> f32 {
+    *top -= 1;
+    f(stack[*top])
+}
+
+#[inline(always)]
+fn binop(stack: &[f32], top: &mut usize, f: impl Fn(f32, f32) -> f32) -> f32 {
+    *top -= 2;
+    f(stack[*top], stack[*top + 1])
+}
+
+#[inline(always)]
+fn ternop(stack: &[f32], top: &mut usize, f: impl Fn(f32, f32, f32) -> f32) -> f32 {
+    *top -= 3;
+    f(stack[*top], stack[*top + 1], stack[*top + 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::func_gen::tests::{assert_matches_eval, sample_trees};
+
+    #[test]
+    fn runs_like_eval() {
+        for tree in sample_trees() {
+            let program = Program::compile(&tree);
+            assert_matches_eval(&tree, |x, y, time| {
+                program.run(x, y, time, &mut program.stack())
+            });
+        }
+    }
+}
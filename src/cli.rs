@@ -0,0 +1,415 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+
+use crate::{
+    animation::AnimationTime,
+    artwork::{Artwork, Channels},
+    batch::{parse_seeds, sheet_url, ContactSheet},
+    formula::to_formula,
+    frames::FrameExport,
+    func_gen::MAX_DEPTH,
+    grammar::{Grammar, GRAMMAR_PATH},
+    render::{generate_image, render_pixels, CpuBackend},
+    screenshot::ImageMetadata,
+    supersampling::Supersampling,
+    video::VideoExport,
+    IMAGE_HEIGHT, IMAGE_WIDTH,
+};
+
+const USAGE: &str = "usage: bevy_randomart [--load ARTWORK.ron|json] [--grammar GRAMMAR]
+   or: bevy_randomart --headless [--seed N | --load ARTWORK.ron|json | --formulas 'R; G; B']
+       [--width N] [--height N] [--depth N] [--time SECONDS] [--grammar GRAMMAR] [--shared]
+       [--samples N] [--dump ARTWORK.ron|json] [--print] --out FILE.png
+   or: bevy_randomart --headless --frames N [--fps N] [--seed N | --load ARTWORK.ron|json
+       | --formulas 'R; G; B'] [--width N] [--height N] [--depth N] [--time SECONDS]
+       [--grammar GRAMMAR] [--shared] [--samples N] --out FILE.gif|FILE.png|DIRECTORY
+   or: bevy_randomart --headless --duration SECONDS [--fps N] [--seed N | --load ARTWORK.ron|json
+       | --formulas 'R; G; B'] [--width N] [--height N] [--depth N] [--time SECONDS]
+       [--grammar GRAMMAR] [--shared] [--samples N] --out VIDEO.mp4|- (raw RGBA frames)
+   or: bevy_randomart --headless --seeds 0..100|4,8,15 [--tile N] [--columns N]
+       [--html INDEX.html] [--depth N] [--time SECONDS] [--grammar GRAMMAR] [--shared]
+       [--samples N] --out SHEET.png
+GRAMMAR is a path inside the assets directory, like grammars/extended.grammar";
+
+/// What a headless run writes to `--out`
+#[derive(Debug)]
+pub enum HeadlessMode {
+    Single {
+        load: Option<PathBuf>,
+        /// `r; g; b` formulas to render instead of a seed
+        formulas: Option<String>,
+        dump: Option<PathBuf>,
+        /// Print the r, g and b formulas
+        print: bool,
+        /// Render a period of frames from `--time` on instead of one image
+        animation: Option<FrameExport>,
+        /// Stream `--duration` seconds of frames from `--time` on instead
+        video: Option<VideoExport>,
+    },
+    Batch {
+        sheet: ContactSheet,
+        html: Option<PathBuf>,
+    },
+}
+
+/// Options for rendering to a file without opening a window
+#[derive(Debug)]
+pub struct HeadlessOptions {
+    pub seed: u64,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub time: f32,
+    /// Path of the grammar inside the assets directory, like windowed runs
+    /// take it. The bundled `default.grammar` when `None`
+    pub grammar: Option<PathBuf>,
+    /// How the generated r, g and b trees relate, `--shared` mixes them with
+    /// a common base
+    pub channels: Channels,
+    /// `--samples` per pixel along each axis
+    pub supersampling: Supersampling,
+    pub out: PathBuf,
+    pub mode: HeadlessMode,
+}
+
+impl HeadlessOptions {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut seed = None;
+        let mut width = IMAGE_WIDTH;
+        let mut height = IMAGE_HEIGHT;
+        let mut depth = MAX_DEPTH;
+        let mut time = 0.;
+        let mut grammar = None;
+        let mut out = None;
+        let mut seeds = None;
+        let mut tile = 128;
+        let mut columns = 8;
+        let mut html = None;
+        let mut load = None;
+        let mut dump = None;
+        let mut formulas = None;
+        let mut print = false;
+        let mut channels = Channels::Independent;
+        let mut supersampling = Supersampling::default();
+        let mut frames = None;
+        let mut fps = 30;
+        let mut duration = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for `{}`\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--headless" => {}
+                "--seed" => seed = Some(parse_value(arg, value()?)?),
+                "--width" => width = parse_value(arg, value()?)?,
+                "--height" => height = parse_value(arg, value()?)?,
+                "--depth" => depth = parse_value(arg, value()?)?,
+                "--time" => time = parse_value(arg, value()?)?,
+                "--grammar" => grammar = Some(PathBuf::from(value()?)),
+                "--out" => out = Some(PathBuf::from(value()?)),
+                "--seeds" => seeds = Some(parse_seeds(value()?)?),
+                "--tile" => tile = parse_value(arg, value()?)?,
+                "--columns" => columns = parse_value(arg, value()?)?,
+                "--html" => html = Some(PathBuf::from(value()?)),
+                "--load" => load = Some(PathBuf::from(value()?)),
+                "--dump" => dump = Some(PathBuf::from(value()?)),
+                "--formulas" => formulas = Some(value()?.clone()),
+                "--print" => print = true,
+                "--shared" => channels = Channels::Shared,
+                "--samples" => supersampling.samples = parse_value(arg, value()?)?,
+                "--frames" => frames = Some(parse_value(arg, value()?)?),
+                "--fps" => fps = parse_value(arg, value()?)?,
+                "--duration" => duration = Some(parse_value(arg, value()?)?),
+                _ => return Err(format!("unknown argument `{}`\n{}", arg, USAGE)),
+            }
+        }
+
+        if width == 0 || height == 0 || tile == 0 || columns == 0 || supersampling.samples == 0 {
+            return Err(
+                "`--width`, `--height`, `--tile`, `--columns` and `--samples` must be greater than 0"
+                    .to_string(),
+            );
+        }
+
+        if frames == Some(0) || !(1..=u16::MAX as u32).contains(&fps) {
+            return Err(
+                "`--frames` must be greater than 0 and `--fps` between 1 and 65535".to_string(),
+            );
+        }
+        let animation = frames.map(|frames| FrameExport { frames, fps });
+        let video = duration.map(|duration| VideoExport { duration, fps });
+
+        match (&animation, &video, &out) {
+            (Some(_), Some(_), _) => {
+                return Err(format!(
+                    "`--frames` and `--duration` can't be used together\n{}",
+                    USAGE
+                ))
+            }
+            (_, Some(video), _) if video.frames() == 0 => {
+                return Err("`--duration` must be at least one frame long".to_string())
+            }
+            (_, None, Some(out)) if out == Path::new("-") => {
+                return Err(format!("`--out -` needs `--duration`\n{}", USAGE))
+            }
+            // Frames on stdout can't be mixed with messages
+            (_, Some(_), Some(out)) if out == Path::new("-") && (print || dump.is_some()) => {
+                return Err("`--print` and `--dump` can't be used with `--out -`".to_string())
+            }
+            _ => {}
+        }
+
+        if load.is_some() && formulas.is_some() {
+            return Err(format!(
+                "`--load` and `--formulas` can't be used together\n{}",
+                USAGE
+            ));
+        }
+
+        let mode = match seeds {
+            Some(_)
+                if load.is_some()
+                    || dump.is_some()
+                    || formulas.is_some()
+                    || print
+                    || animation.is_some()
+                    || video.is_some() =>
+            {
+                return Err(format!(
+                    "`--load`, `--formulas`, `--dump`, `--print`, `--frames` and `--duration` can't be used with `--seeds`\n{}",
+                    USAGE
+                ))
+            }
+            Some(seeds) => HeadlessMode::Batch {
+                sheet: ContactSheet::new(seeds, tile, columns)?,
+                html,
+            },
+            None if html.is_some() => return Err(format!("`--html` needs `--seeds`\n{}", USAGE)),
+            None => HeadlessMode::Single {
+                load,
+                formulas,
+                dump,
+                print,
+                animation,
+                video,
+            },
+        };
+
+        Ok(HeadlessOptions {
+            seed: seed.unwrap_or_else(rand::random),
+            width,
+            height,
+            depth,
+            time,
+            grammar,
+            channels,
+            supersampling,
+            out: out.ok_or_else(|| format!("missing `--out`\n{}", USAGE))?,
+            mode,
+        })
+    }
+}
+
+/// Options of a windowed run
+#[derive(Debug)]
+pub struct AppOptions {
+    /// Artwork to open instead of the seed's
+    pub loaded: Option<Artwork>,
+    /// Asset path of the grammar, hot reloaded like the other assets
+    pub grammar: String,
+}
+
+/// Parse the arguments of a windowed run
+pub fn parse_app_args(args: &[String]) -> Result<AppOptions, String> {
+    let mut options = AppOptions {
+        loaded: None,
+        grammar: GRAMMAR_PATH.to_string(),
+    };
+
+    for pair in args.chunks(2) {
+        match pair {
+            [flag, path] if flag == "--load" => {
+                options.loaded = Some(Artwork::load(Path::new(path))?)
+            }
+            [flag, path] if flag == "--grammar" => options.grammar = path.clone(),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(options)
+}
+
+/// Parse the `value` given for the flag or metadata `name`
+pub fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, name))
+}
+
+/// Parse `r; g; b` into an artwork without a seed
+fn parse_formulas(text: &str) -> Result<Artwork, String> {
+    let channels: Vec<&str> = text.split(';').collect();
+    let [r, g, b] = channels[..] else {
+        return Err(format!(
+            "`--formulas` needs three formulas separated by `;`, got {}",
+            channels.len()
+        ));
+    };
+    Artwork::from_formulas([r, g, b])
+}
+
+/// Directory windowed runs load their assets from, found the way Bevy's
+/// `FileAssetReader` does
+fn assets_dir() -> PathBuf {
+    let base = std::env::var_os("BEVY_ASSET_ROOT")
+        .or_else(|| std::env::var_os("CARGO_MANIFEST_DIR"))
+        .map(PathBuf::from)
+        .or_else(|| Some(std::env::current_exe().ok()?.parent()?.to_path_buf()))
+        .unwrap_or_default();
+    base.join("assets")
+}
+
+pub fn load_grammar(path: &Path) -> Result<Grammar, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|error| format!("could not read {}: {}", path.display(), error))?;
+    source
+        .parse()
+        .map_err(|error| format!("invalid grammar {}:{}", path.display(), error))
+}
+
+/// Write `image` in the format picked from the extension of `path`
+pub fn save_image(image: Image, path: &Path) -> Result<(), String> {
+    image
+        .try_into_dynamic()
+        .map_err(|error| error.to_string())?
+        .save(path)
+        .map_err(|error| format!("could not write {}: {}", path.display(), error))
+}
+
+/// Render on the CPU and save the result, no window or GPU needed
+pub fn run_headless(options: HeadlessOptions) -> Result<(), String> {
+    ComputeTaskPool::get_or_init(TaskPool::default);
+
+    // Embedded so headless runs work from any directory
+    let grammar = match &options.grammar {
+        Some(path) => load_grammar(&assets_dir().join(path))?,
+        None => include_str!("../assets/grammars/default.grammar")
+            .parse()
+            .map_err(|error| format!("invalid grammar default.grammar:{}", error))?,
+    };
+    let time = AnimationTime::default().at(options.time);
+
+    match &options.mode {
+        HeadlessMode::Single {
+            load,
+            formulas,
+            dump,
+            print,
+            animation,
+            video,
+        } => {
+            let artwork = match (load, formulas) {
+                (Some(path), _) => Artwork::load(path)?,
+                (None, Some(formulas)) => parse_formulas(formulas)?,
+                (None, None) => {
+                    Artwork::generate(&grammar, options.seed, options.depth, options.channels)
+                }
+            };
+
+            if *print {
+                println!("r = {}", to_formula(&artwork.r));
+                println!("g = {}", to_formula(&artwork.g));
+                println!("b = {}", to_formula(&artwork.b));
+            }
+
+            if let Some(path) = dump {
+                artwork.save(path)?;
+                println!("artwork written to {}", path.display());
+            }
+
+            if let Some(video) = video {
+                video.stream(
+                    &artwork,
+                    (options.width, options.height),
+                    options.supersampling,
+                    options.time,
+                    &options.out,
+                )?;
+                let message = format!(
+                    "{} frames written to {}",
+                    video.frames(),
+                    options.out.display()
+                );
+                // stdout may be carrying the frames
+                match options.out == Path::new("-") {
+                    true => eprintln!("{}", message),
+                    false => println!("{}", message),
+                }
+                return Ok(());
+            }
+
+            if let Some(animation) = animation {
+                let metadata = ImageMetadata::new(&artwork, options.depth, &grammar, time);
+                animation.save(
+                    &artwork,
+                    &metadata,
+                    (options.width, options.height),
+                    options.supersampling,
+                    &options.out,
+                )?;
+                println!(
+                    "{} frames written to {}",
+                    animation.frames,
+                    options.out.display()
+                );
+                return Ok(());
+            }
+
+            let mut image = generate_image(options.width, options.height);
+            render_pixels(
+                &mut image,
+                &artwork,
+                time,
+                CpuBackend::default(),
+                options.supersampling,
+            );
+            save_image(image, &options.out)?;
+            match artwork.seed {
+                Some(seed) => println!("seed {} written to {}", seed, options.out.display()),
+                None => println!("artwork written to {}", options.out.display()),
+            }
+        }
+        HeadlessMode::Batch { sheet, html } => {
+            save_image(
+                sheet.render(
+                    &grammar,
+                    options.depth,
+                    options.channels,
+                    options.supersampling,
+                    time,
+                ),
+                &options.out,
+            )?;
+            println!(
+                "{} seeds written to {}",
+                sheet.seeds.len(),
+                options.out.display()
+            );
+
+            if let Some(html) = html {
+                let index = sheet.html_index(&sheet_url(&options.out, html));
+                std::fs::write(html, index)
+                    .map_err(|error| format!("could not write {}: {}", html.display(), error))?;
+                println!("index written to {}", html.display());
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+
+/// Seconds of animation both renderers draw at, instead of the wall clock.
+/// The GPU receives `AnimationTime::at` of it computed on the CPU, so equal
+/// clocks give equal `t` values everywhere
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct RenderClock {
+    pub seconds: f32,
+    pub mode: ClockMode,
+    /// Increment of `ClockMode::Fixed` and of the `,` and `.` steps
+    pub step: f32,
+}
+
+impl Default for RenderClock {
+    fn default() -> Self {
+        RenderClock {
+            seconds: 0.,
+            mode: ClockMode::default(),
+            step: 1. / 60.,
+        }
+    }
+}
+
+/// How `RenderClock` advances every frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockMode {
+    /// Along with the wall clock
+    #[default]
+    RealTime,
+    Paused,
+    /// By exactly `step`, however long the frame took
+    Fixed,
+}
+
+/// Seconds scrubbed per second of holding an arrow key
+const SCRUB_SPEED: f32 = 2.;
+
+/// Space pauses, the arrow keys scrub, `,` and `.` step by a single
+/// increment and `T` switches to fixed steps
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RenderClock>()
+            .add_systems(PreUpdate, advance_clock)
+            .add_systems(
+                Update,
+                toggle_pause.run_if(input_just_pressed(KeyCode::Space)),
+            )
+            .add_systems(
+                Update,
+                toggle_fixed.run_if(input_just_pressed(KeyCode::KeyT)),
+            )
+            .add_systems(Update, (scrub, step));
+    }
+}
+
+// Only touches the clock when it moves, so a paused clock isn't redrawn
+fn advance_clock(mut clock: ResMut<RenderClock>, time: Res<Time>) {
+    let delta = match clock.mode {
+        ClockMode::RealTime => time.delta_secs(),
+        ClockMode::Paused => return,
+        ClockMode::Fixed => clock.step,
+    };
+    clock.seconds += delta;
+}
+
+fn toggle_pause(mut clock: ResMut<RenderClock>) {
+    clock.mode = match clock.mode {
+        ClockMode::Paused => ClockMode::RealTime,
+        _ => ClockMode::Paused,
+    };
+    info!("clock: {:?} at {}s", clock.mode, clock.seconds);
+}
+
+fn toggle_fixed(mut clock: ResMut<RenderClock>) {
+    clock.mode = match clock.mode {
+        ClockMode::Fixed => ClockMode::RealTime,
+        _ => ClockMode::Fixed,
+    };
+    info!("clock: {:?} at {}s", clock.mode, clock.seconds);
+}
+
+fn scrub(mut clock: ResMut<RenderClock>, keys: Res<ButtonInput<KeyCode>>, time: Res<Time>) {
+    let direction =
+        keys.pressed(KeyCode::ArrowRight) as i32 - keys.pressed(KeyCode::ArrowLeft) as i32;
+    if direction != 0 {
+        clock.seconds += direction as f32 * SCRUB_SPEED * time.delta_secs();
+    }
+}
+
+fn step(mut clock: ResMut<RenderClock>, keys: Res<ButtonInput<KeyCode>>) {
+    let steps =
+        keys.just_pressed(KeyCode::Period) as i32 - keys.just_pressed(KeyCode::Comma) as i32;
+    if steps != 0 {
+        clock.seconds += steps as f32 * clock.step;
+        info!("clock: {}s", clock.seconds);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::func_gen::{
+    safe_div, safe_log, safe_pow, safe_smoothstep, saw, NodeBinop, NodeKind, NodeTernop, NodeUnop,
+};
+
+/// A tree compiled into nested closures taking `x`, `y` and `time`
+pub type Function = Box<dyn Fn(f32, f32, f32) -> f32 + Send + Sync>;
+
+/// Build a closure computing the same values as `eval` for `node`
+pub fn compile(node: &NodeKind) -> Function {
+    match node {
+        NodeKind::X => Box::new(|x, _, _| x),
+        NodeKind::Y => Box::new(|_, y, _| y),
+        NodeKind::Random(r) => {
+            let r = *r;
+            Box::new(move |_, _, _| r)
+        }
+        NodeKind::Add(node_binop) => binop(node_binop, |a, b| a + b),
+        NodeKind::Mult(node_binop) => binop(node_binop, |a, b| a * b),
+        NodeKind::Sqrt(node_unop) => unop(node_unop, |v| v.abs().sqrt()),
+        NodeKind::Abs(node_unop) => unop(node_unop, f32::abs),
+        NodeKind::Sin(node_unop) => unop(node_unop, f32::sin),
+        NodeKind::Mod(node_binop) => binop(node_binop, |a, b| a % b),
+        NodeKind::Gt(node_binop) => binop(node_binop, |a, b| (a > b) as i32 as f32),
+        NodeKind::Time => Box::new(|_, _, time| time.sin()),
+        NodeKind::Sub(node_binop) => binop(node_binop, |a, b| a - b),
+        NodeKind::Div(node_binop) => binop(node_binop, safe_div),
+        NodeKind::Cos(node_unop) => unop(node_unop, f32::cos),
+        NodeKind::Tan(node_unop) => unop(node_unop, f32::tan),
+        NodeKind::Exp(node_unop) => unop(node_unop, f32::exp),
+        NodeKind::Log(node_unop) => unop(node_unop, safe_log),
+        NodeKind::Pow(node_binop) => binop(node_binop, safe_pow),
+        NodeKind::Min(node_binop) => binop(node_binop, f32::min),
+        NodeKind::Max(node_binop) => binop(node_binop, f32::max),
+        NodeKind::Atan2(node_binop) => binop(node_binop, f32::atan2),
+        NodeKind::Floor(node_unop) => unop(node_unop, f32::floor),
+        NodeKind::Fract(node_unop) => unop(node_unop, |v| v - v.floor()),
+        NodeKind::Mix(node_ternop) => ternop(node_ternop, |a, b, t| a * (1. - t) + b * t),
+        NodeKind::Smoothstep(node_ternop) => ternop(node_ternop, safe_smoothstep),
+        NodeKind::Clamp(node_ternop) => ternop(node_ternop, |v, low, high| v.max(low).min(high)),
+        NodeKind::Lt(node_binop) => binop(node_binop, |a, b| (a < b) as i32 as f32),
+        NodeKind::If(node_ternop) => {
+            let condition = compile(&node_ternop.a);
+            let then = compile(&node_ternop.b);
+            let otherwise = compile(&node_ternop.c);
+            Box::new(move |x, y, time| match condition(x, y, time) > 0. {
+                true => then(x, y, time),
+                false => otherwise(x, y, time),
+            })
+        }
+        NodeKind::Radius => Box::new(|x, y, _| (x * x + y * y).sqrt()),
+        NodeKind::Angle => Box::new(|x, y, _| y.atan2(x)),
+        NodeKind::DistTo(px, py) => {
+            let (px, py) = (*px, *py);
+            Box::new(move |x, y, _| ((x - px) * (x - px) + (y - py) * (y - py)).sqrt())
+        }
+        NodeKind::T => Box::new(|_, _, time| time),
+        NodeKind::SinT(freq) => {
+            let freq = *freq;
+            Box::new(move |_, _, time| (time * freq).sin())
+        }
+        NodeKind::Saw => Box::new(|_, _, time| saw(time)),
+    }
+}
+
+fn unop(node_unop: &NodeUnop, f: impl Fn(f32) -> f32 + Send + Sync + 'static) -> Function {
+    let value = compile(&node_unop.value);
+    Box::new(move |x, y, time| f(value(x, y, time)))
+}
+
+fn binop(node_binop: &NodeBinop, f: impl Fn(f32, f32) -> f32 + Send + Sync + 'static) -> Function {
+    let lhs = compile(&node_binop.lhs);
+    // A constant right hand side is common after `simplify`, so it skips a call
+    if let NodeKind::Random(r) = *node_binop.rhs {
+        return Box::new(move |x, y, time| f(lhs(x, y, time), r));
+    }
+    let rhs = compile(&node_binop.rhs);
+    Box::new(move |x, y, time| f(lhs(x, y, time), rhs(x, y, time)))
+}
+
+fn ternop(
+    node_ternop: &NodeTernop,
+    f: impl Fn(f32, f32, f32) -> f32 + Send + Sync + 'static,
+) -> Function {
+    let a = compile(&node_ternop.a);
+    let b = compile(&node_ternop.b);
+    let c = compile(&node_ternop.c);
+    Box::new(move |x, y, time| f(a(x, y, time), b(x, y, time), c(x, y, time)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        func_gen::tests::{assert_matches_eval, sample_trees},
+        grammar::Op,
+        optimize::simplify,
+    };
+
+    fn assert_compiles_like_eval(tree: &NodeKind) {
+        assert_matches_eval(tree, compile(tree));
+    }
+
+    #[test]
+    fn compiles_like_eval() {
+        for tree in sample_trees() {
+            assert_compiles_like_eval(&tree);
+            // Simplified trees have more constant right hand sides
+            assert_compiles_like_eval(&simplify(&tree));
+        }
+    }
+
+    #[test]
+    fn constant_right_hand_sides_compile_like_eval() {
+        let binops = [
+            Op::Add,
+            Op::Mult,
+            Op::Mod,
+            Op::Gt,
+            Op::Sub,
+            Op::Div,
+            Op::Pow,
+            Op::Min,
+            Op::Max,
+            Op::Atan2,
+            Op::Lt,
+        ];
+        for op in binops {
+            for rhs in [0., -0.5, 1., 3.] {
+                let tree = op
+                    .apply(vec![
+                        Op::Sin.apply(vec![NodeKind::X]).unwrap(),
+                        NodeKind::Random(rhs),
+                    ])
+                    .unwrap();
+                assert_compiles_like_eval(&tree);
+            }
+        }
+    }
+}
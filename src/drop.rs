@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+
+use crate::{
+    animation::AnimationTime,
+    artwork::{Artwork, ArtworkSource, Channels},
+    clock::{ClockMode, RenderClock},
+    formula::to_formula,
+    func_gen::MAX_DEPTH,
+    grammar::Grammar,
+    screenshot::ImageMetadata,
+    seed::Seed,
+};
+
+/// Dropping a screenshot or export on the window brings its artwork back,
+/// through the `Seed` when it regenerates the same trees or else from the
+/// formulas stored in the image. The clock is paused at the image's time
+pub struct DropPlugin;
+
+impl Plugin for DropPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, drop_image);
+    }
+}
+
+fn drop_image(
+    mut commands: Commands,
+    mut events: EventReader<FileDragAndDrop>,
+    grammar: Res<Grammar>,
+    channels: Res<Channels>,
+    mut seed: ResMut<Seed>,
+    mut source: ResMut<ArtworkSource>,
+    mut clock: ResMut<RenderClock>,
+    animation: Res<AnimationTime>,
+) {
+    for event in events.read() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = event else {
+            continue;
+        };
+
+        let metadata = match ImageMetadata::load_png(path_buf) {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                error!("{}", error);
+                continue;
+            }
+        };
+        clock.seconds = animation.seconds(metadata.time);
+        clock.mode = ClockMode::Paused;
+
+        if let Some(number) = metadata
+            .seed
+            .filter(|&number| regenerates(&metadata, &grammar, number, *channels))
+        {
+            seed.0 = number;
+            continue;
+        }
+
+        // Another grammar or channel mode, the formulas still give the image
+        match metadata.artwork() {
+            Ok(artwork) => {
+                commands.insert_resource(artwork);
+                *source = ArtworkSource::Loaded;
+            }
+            Err(error) => error!("{}", error),
+        }
+    }
+}
+
+// Whether the seed gives back the stored formulas with the current settings
+fn regenerates(metadata: &ImageMetadata, grammar: &Grammar, seed: u64, channels: Channels) -> bool {
+    if metadata.depth != MAX_DEPTH || metadata.grammar != grammar.version() {
+        return false;
+    }
+    let artwork = Artwork::generate(grammar, seed, MAX_DEPTH, channels);
+    [&artwork.r, &artwork.g, &artwork.b].map(to_formula) == metadata.formulas
+}
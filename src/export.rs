@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use bevy::{
+    input::common_conditions::input_just_pressed,
+    prelude::*,
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
+};
+
+use crate::{
+    animation::AnimationTime,
+    artwork::Artwork,
+    clock::RenderClock,
+    func_gen::MAX_DEPTH,
+    grammar::Grammar,
+    render::{generate_image, render_pixels, CpuBackend},
+    screenshot::ImageMetadata,
+    supersampling::Supersampling,
+};
+
+/// Renders the artwork on the CPU at a size unrelated to the window and
+/// saves it as a PNG with its `ImageMetadata`. `E` exports at `ExportSize`,
+/// other sizes can be requested by sending an `ExportRequest`
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ExportSize>()
+            .init_resource::<ExportTasks>()
+            .add_event::<ExportRequest>()
+            .add_systems(
+                Update,
+                request_export.run_if(input_just_pressed(KeyCode::KeyE)),
+            )
+            .add_systems(Update, (start_exports, finish_exports).chain());
+    }
+}
+
+/// Size of the images exported with `E`
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ExportSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for ExportSize {
+    fn default() -> Self {
+        ExportSize {
+            width: 4096,
+            height: 4096,
+        }
+    }
+}
+
+/// Render the current artwork and frame at `width`×`height` into `path`
+#[derive(Event, Debug, Clone)]
+pub struct ExportRequest {
+    pub width: u32,
+    pub height: u32,
+    pub path: PathBuf,
+}
+
+/// Exports being rendered and written in the background
+#[derive(Resource, Default)]
+struct ExportTasks(Vec<Task<Result<PathBuf, String>>>);
+
+fn request_export(
+    size: Res<ExportSize>,
+    artwork: Res<Artwork>,
+    mut requests: EventWriter<ExportRequest>,
+) {
+    let name = match artwork.seed {
+        Some(seed) => format!("artwork-{}-{}x{}.png", seed, size.width, size.height),
+        None => format!("artwork-{}x{}.png", size.width, size.height),
+    };
+    requests.send(ExportRequest {
+        width: size.width,
+        height: size.height,
+        path: PathBuf::from(name),
+    });
+}
+
+fn start_exports(
+    mut requests: EventReader<ExportRequest>,
+    mut tasks: ResMut<ExportTasks>,
+    artwork: Res<Artwork>,
+    grammar: Res<Grammar>,
+    clock: Res<RenderClock>,
+    animation: Res<AnimationTime>,
+    backend: Res<CpuBackend>,
+    supersampling: Res<Supersampling>,
+) {
+    for request in requests.read() {
+        info!(
+            "exporting {}x{} to {}",
+            request.width,
+            request.height,
+            request.path.display()
+        );
+
+        let request = request.clone();
+        let artwork = artwork.clone();
+        let time = animation.at(clock.seconds);
+        let metadata = ImageMetadata::new(&artwork, MAX_DEPTH, &grammar, time);
+        let (backend, supersampling) = (*backend, *supersampling);
+        tasks.0.push(AsyncComputeTaskPool::get().spawn(async move {
+            let mut image = generate_image(request.width, request.height);
+            render_pixels(&mut image, &artwork, time, backend, supersampling);
+            metadata
+                .save_png(&image, &request.path)
+                .map(|()| request.path)
+        }));
+    }
+}
+
+fn finish_exports(mut tasks: ResMut<ExportTasks>) {
+    tasks.0.retain_mut(|task| match block_on(poll_once(task)) {
+        Some(Ok(path)) => {
+            info!("exported {}", path.display());
+            false
+        }
+        Some(Err(error)) => {
+            error!("{}", error);
+            false
+        }
+        None => true,
+    });
+}
@@ -0,0 +1,451 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use crate::{
+    func_gen::{NodeBinop, NodeKind, NodeTernop, NodeUnop},
+    grammar::Op,
+};
+
+/// Precedence of the infix operators, function calls and terminals bind tightest
+const COMPARISON: u8 = 1;
+const SUM: u8 = 2;
+const PRODUCT: u8 = 3;
+const ATOM: u8 = 4;
+
+/// Write `node` in math notation, e.g. `sin(x * 0.31) + abs(y)`. The result
+/// parses back into the same tree
+pub fn to_formula(node: &NodeKind) -> String {
+    let unop =
+        |name: &str, node_unop: &NodeUnop| format!("{}({})", name, to_formula(&node_unop.value));
+    let call = |name: &str, node_binop: &NodeBinop| {
+        format!(
+            "{}({}, {})",
+            name,
+            to_formula(&node_binop.lhs),
+            to_formula(&node_binop.rhs)
+        )
+    };
+    let ternop = |name: &str, node_ternop: &NodeTernop| {
+        format!(
+            "{}({}, {}, {})",
+            name,
+            to_formula(&node_ternop.a),
+            to_formula(&node_ternop.b),
+            to_formula(&node_ternop.c)
+        )
+    };
+
+    match node {
+        NodeKind::X => "x".to_string(),
+        NodeKind::Y => "y".to_string(),
+        NodeKind::Random(r) => format!("{}", r),
+        NodeKind::Add(node_binop) => infix("+", SUM, node_binop),
+        NodeKind::Sub(node_binop) => infix("-", SUM, node_binop),
+        NodeKind::Mult(node_binop) => infix("*", PRODUCT, node_binop),
+        NodeKind::Div(node_binop) => infix("/", PRODUCT, node_binop),
+        NodeKind::Mod(node_binop) => infix("%", PRODUCT, node_binop),
+        NodeKind::Gt(node_binop) => infix(">", COMPARISON, node_binop),
+        NodeKind::Lt(node_binop) => infix("<", COMPARISON, node_binop),
+        NodeKind::Sqrt(node_unop) => unop("sqrt", node_unop),
+        NodeKind::Abs(node_unop) => unop("abs", node_unop),
+        NodeKind::Sin(node_unop) => unop("sin", node_unop),
+        NodeKind::Cos(node_unop) => unop("cos", node_unop),
+        NodeKind::Tan(node_unop) => unop("tan", node_unop),
+        NodeKind::Exp(node_unop) => unop("exp", node_unop),
+        NodeKind::Log(node_unop) => unop("log", node_unop),
+        NodeKind::Floor(node_unop) => unop("floor", node_unop),
+        NodeKind::Fract(node_unop) => unop("fract", node_unop),
+        NodeKind::Pow(node_binop) => call("pow", node_binop),
+        NodeKind::Min(node_binop) => call("min", node_binop),
+        NodeKind::Max(node_binop) => call("max", node_binop),
+        NodeKind::Atan2(node_binop) => call("atan2", node_binop),
+        NodeKind::Mix(node_ternop) => ternop("mix", node_ternop),
+        NodeKind::Smoothstep(node_ternop) => ternop("smoothstep", node_ternop),
+        NodeKind::Clamp(node_ternop) => ternop("clamp", node_ternop),
+        NodeKind::If(node_ternop) => ternop("if", node_ternop),
+        NodeKind::Time => "time".to_string(),
+        NodeKind::Radius => "radius".to_string(),
+        NodeKind::Angle => "angle".to_string(),
+        NodeKind::DistTo(px, py) => format!("dist_to({}, {})", px, py),
+        NodeKind::T => "t".to_string(),
+        NodeKind::SinT(freq) => format!("sin_t({})", freq),
+        NodeKind::Saw => "saw".to_string(),
+    }
+}
+
+fn precedence(node: &NodeKind) -> u8 {
+    match node {
+        NodeKind::Gt(_) | NodeKind::Lt(_) => COMPARISON,
+        NodeKind::Add(_) | NodeKind::Sub(_) => SUM,
+        NodeKind::Mult(_) | NodeKind::Div(_) | NodeKind::Mod(_) => PRODUCT,
+        _ => ATOM,
+    }
+}
+
+// Operators group to the left, so only a right operand of the same
+// precedence needs parentheses
+fn infix(symbol: &str, precedence: u8, node_binop: &NodeBinop) -> String {
+    format!(
+        "{} {} {}",
+        operand(&node_binop.lhs, precedence),
+        symbol,
+        operand(&node_binop.rhs, precedence + 1)
+    )
+}
+
+fn operand(node: &NodeKind, min_precedence: u8) -> String {
+    match precedence(node) < min_precedence {
+        true => format!("({})", to_formula(node)),
+        false => to_formula(node),
+    }
+}
+
+/// Error in a formula, columns start at 1
+#[derive(Debug, Clone)]
+pub struct FormulaError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl FormulaError {
+    fn new(column: usize, message: String) -> Self {
+        FormulaError { column, message }
+    }
+}
+
+impl Display for FormulaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl Error for FormulaError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f32),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Greater,
+    Less,
+    OpenParen,
+    CloseParen,
+    Comma,
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, FormulaError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let token = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '>' => Token::Greater,
+            '<' => Token::Less,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ',' => Token::Comma,
+            c if c.is_alphabetic() || c == '_' => {
+                while i + 1 < chars.len() && (chars[i + 1].is_alphanumeric() || chars[i + 1] == '_')
+                {
+                    i += 1;
+                }
+                Token::Ident(chars[start..=i].iter().collect())
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                while i + 1 < chars.len() && (chars[i + 1].is_ascii_digit() || chars[i + 1] == '.')
+                {
+                    i += 1;
+                }
+                let number: String = chars[start..=i].iter().collect();
+                match number.parse::<f32>() {
+                    Ok(value) if value.is_finite() => Token::Number(value),
+                    // Would print as `inf`, which neither formulas nor WGSL accept
+                    Ok(_) => {
+                        return Err(FormulaError::new(
+                            start + 1,
+                            format!("number `{}` is too large", number),
+                        ))
+                    }
+                    Err(_) => {
+                        return Err(FormulaError::new(
+                            start + 1,
+                            format!("invalid number `{}`", number),
+                        ))
+                    }
+                }
+            }
+            c => {
+                return Err(FormulaError::new(
+                    start + 1,
+                    format!("unexpected character `{}`", c),
+                ))
+            }
+        };
+        tokens.push((start + 1, token));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens of a formula, one method per precedence
+struct FormulaParser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl FormulaParser {
+    fn error<T>(&self, column: usize, message: String) -> Result<T, FormulaError> {
+        Err(FormulaError::new(column, message))
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |(column, _)| *column)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), FormulaError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            self.error(self.column(), format!("expected {}", expected))
+        }
+    }
+
+    /// Parse one level of left associative operators
+    fn binary(
+        &mut self,
+        operators: &[(Token, Op)],
+        operand: fn(&mut Self) -> Result<NodeKind, FormulaError>,
+    ) -> Result<NodeKind, FormulaError> {
+        let mut lhs = operand(self)?;
+        while let Some((_, op)) = operators
+            .iter()
+            .find(|(token, _)| self.peek() == Some(token))
+        {
+            self.pos += 1;
+            let rhs = operand(self)?;
+            lhs = op.apply(vec![lhs, rhs]).unwrap();
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<NodeKind, FormulaError> {
+        self.binary(
+            &[(Token::Greater, Op::Gt), (Token::Less, Op::Lt)],
+            Self::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<NodeKind, FormulaError> {
+        self.binary(
+            &[(Token::Plus, Op::Add), (Token::Minus, Op::Sub)],
+            Self::product,
+        )
+    }
+
+    fn product(&mut self) -> Result<NodeKind, FormulaError> {
+        self.binary(
+            &[
+                (Token::Star, Op::Mult),
+                (Token::Slash, Op::Div),
+                (Token::Percent, Op::Mod),
+            ],
+            Self::negation,
+        )
+    }
+
+    // There's no negation node, `-x` becomes `-1 * x`
+    fn negation(&mut self) -> Result<NodeKind, FormulaError> {
+        if !self.eat(&Token::Minus) {
+            return self.atom();
+        }
+        match self.negation()? {
+            NodeKind::Random(r) => Ok(NodeKind::Random(-r)),
+            node => Ok(NodeKind::Mult(NodeBinop::new(
+                Box::new(NodeKind::Random(-1.)),
+                Box::new(node),
+            ))),
+        }
+    }
+
+    fn atom(&mut self) -> Result<NodeKind, FormulaError> {
+        let column = self.column();
+        match self.tokens.get(self.pos).map(|(_, token)| token.clone()) {
+            Some(Token::Number(value)) => {
+                self.pos += 1;
+                Ok(NodeKind::Random(value))
+            }
+            Some(Token::OpenParen) => {
+                self.pos += 1;
+                let node = self.comparison()?;
+                self.expect(Token::CloseParen, "`)`")?;
+                Ok(node)
+            }
+            Some(Token::Ident(name)) => {
+                self.pos += 1;
+                self.call(column, &name)
+            }
+            _ => self.error(column, "expected a number, name or `(`".to_string()),
+        }
+    }
+
+    fn call(&mut self, column: usize, name: &str) -> Result<NodeKind, FormulaError> {
+        let op = match Op::from_name(name) {
+            // Constants are written as plain numbers
+            Some(Op::Random) | None => {
+                return self.error(column, format!("unknown name `{}`", name))
+            }
+            Some(op) => op,
+        };
+
+        let mut args = Vec::new();
+        if self.eat(&Token::OpenParen) {
+            loop {
+                args.push(self.comparison()?);
+                if self.eat(&Token::CloseParen) {
+                    break;
+                }
+                self.expect(Token::Comma, "`,` or `)`")?;
+            }
+        }
+
+        let constants: Vec<f32> = args
+            .iter()
+            .filter_map(|arg| match arg {
+                NodeKind::Random(r) => Some(*r),
+                _ => None,
+            })
+            .collect();
+        match op {
+            Op::DistTo if args.len() == 2 && constants.len() == 2 => {
+                Ok(NodeKind::DistTo(constants[0], constants[1]))
+            }
+            Op::DistTo => self.error(column, "`dist_to` takes two numbers".to_string()),
+            Op::SinT if args.len() == 1 && constants.len() == 1 => Ok(NodeKind::SinT(constants[0])),
+            Op::SinT => self.error(column, "`sin_t` takes one number".to_string()),
+            _ if args.len() != op.arity() => self.error(
+                column,
+                format!(
+                    "`{}` takes {} arguments, got {}",
+                    name,
+                    op.arity(),
+                    args.len()
+                ),
+            ),
+            _ => Ok(op.apply(args).unwrap()),
+        }
+    }
+}
+
+impl FromStr for NodeKind {
+    type Err = FormulaError;
+
+    /// Parse a formula in the notation written by `to_formula`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = FormulaParser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            end: text.chars().count() + 1,
+        };
+
+        let node = parser.comparison()?;
+        if parser.pos != parser.tokens.len() {
+            return parser.error(parser.column(), "expected an operator".to_string());
+        }
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::grammar::Grammar;
+
+    fn binop(op: Op, lhs: NodeKind, rhs: NodeKind) -> NodeKind {
+        op.apply(vec![lhs, rhs]).unwrap()
+    }
+
+    fn assert_round_trip(node: NodeKind) {
+        let formula = to_formula(&node);
+        assert_eq!(formula.parse::<NodeKind>().unwrap(), node, "{}", formula);
+    }
+
+    #[test]
+    fn round_trips_edge_cases() {
+        let (x, y) = (|| NodeKind::X, || NodeKind::Y);
+        let constant = NodeKind::Random;
+
+        for node in [
+            constant(-0.5),
+            constant(-1e-7),
+            binop(Op::Sub, x(), constant(-0.5)),
+            binop(Op::Mult, constant(-0.5), x()),
+            binop(Op::Sub, constant(-0.25), y()),
+            binop(Op::Sub, x(), binop(Op::Sub, y(), constant(0.3))),
+            binop(Op::Sub, binop(Op::Sub, x(), y()), constant(0.3)),
+            binop(Op::Mod, x(), binop(Op::Mod, y(), constant(-0.5))),
+            binop(Op::Div, x(), binop(Op::Mult, y(), x())),
+            binop(Op::Mult, binop(Op::Add, x(), y()), constant(2.)),
+            constant(f32::MAX),
+            binop(Op::Sub, x(), constant(f32::MAX)),
+            NodeKind::DistTo(-0.25, 0.75),
+            NodeKind::SinT(3.),
+            binop(Op::Gt, binop(Op::Gt, x(), y()), constant(0.1)),
+            binop(Op::Gt, x(), binop(Op::Lt, y(), constant(0.2))),
+            binop(Op::Lt, binop(Op::Add, x(), y()), binop(Op::Mod, y(), x())),
+            Op::If
+                .apply(vec![binop(Op::Gt, x(), y()), NodeKind::T, NodeKind::Saw])
+                .unwrap(),
+        ] {
+            assert_round_trip(node);
+        }
+
+        let error = "x + 99999999999999999999999999999999999999999 * y"
+            .parse::<NodeKind>()
+            .unwrap_err();
+        assert_eq!(error.column, 5);
+        assert!(error.message.contains("too large"), "{}", error);
+    }
+
+    #[test]
+    fn round_trips_generated_trees() {
+        let grammar: Grammar = include_str!("../assets/grammars/extended.grammar")
+            .parse()
+            .unwrap();
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            assert_round_trip(grammar.expand(&grammar.entry, 8, &mut rng));
+        }
+    }
+}
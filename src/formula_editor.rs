@@ -0,0 +1,244 @@
+use bevy::{
+    input::{common_conditions::input_just_pressed, InputSystem},
+    prelude::*,
+    ui::FocusPolicy,
+};
+use bevy_simple_text_input::{
+    TextInput, TextInputInactive, TextInputSettings, TextInputSubmitEvent, TextInputSystem,
+    TextInputTextColor, TextInputTextFont, TextInputValue,
+};
+
+use crate::{
+    artwork::{Artwork, ArtworkSource},
+    formula::to_formula,
+    func_gen::NodeKind,
+};
+
+const BORDER_COLOR_ACTIVE: Color = Color::srgb(0.75, 0.52, 0.99);
+const BORDER_COLOR_INACTIVE: Color = Color::srgb(0.25, 0.25, 0.25);
+const TEXT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const ERROR_COLOR: Color = Color::srgb(0.95, 0.4, 0.4);
+const BACKGROUND_COLOR: Color = Color::srgba(0.15, 0.15, 0.15, 0.9);
+const FONT_SIZE: f32 = 20.;
+
+const CHANNELS: [&str; 3] = ["R", "G", "B"];
+
+/// Panel with a formula per color channel, opened with `F`. Enter renders
+/// all three, Tab moves between them and Escape closes the panel
+pub struct FormulaEditorPlugin;
+
+impl Plugin for FormulaEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreUpdate, editor_keys.after(InputSystem))
+            .add_systems(
+                Update,
+                open_editor.run_if(input_just_pressed(KeyCode::KeyF)),
+            )
+            .add_systems(Update, focus_on_click.before(TextInputSystem))
+            .add_systems(Update, apply_formulas.after(TextInputSystem));
+    }
+}
+
+#[derive(Component)]
+struct FormulaEditor;
+
+/// Text input holding the formula of the `n`th channel
+#[derive(Component)]
+struct FormulaInput(usize);
+
+/// Parse error shown below the `n`th channel's input
+#[derive(Component)]
+struct FormulaErrorText(usize);
+
+/// Keys left visible while the editor is open, the text input reads them
+/// from `ButtonInput` for its word and line jumps
+const MODIFIERS: [KeyCode; 8] = [
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+    KeyCode::SuperLeft,
+    KeyCode::SuperRight,
+];
+
+// Handles the editor's own keys and hides every other key from the other
+// plugins' shortcuts while it's open, so typing `sin(r)` doesn't reset the seed
+fn editor_keys(
+    mut commands: Commands,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    editors: Query<Entity, With<FormulaEditor>>,
+    mut inputs: Query<(&FormulaInput, &mut TextInputInactive, &mut BorderColor)>,
+) {
+    let Ok(editor) = editors.get_single() else {
+        return;
+    };
+
+    if keys.just_pressed(KeyCode::Escape) {
+        commands.entity(editor).despawn_recursive();
+    } else if keys.just_pressed(KeyCode::Tab) {
+        let focused = inputs
+            .iter()
+            .find(|(_, inactive, _)| !inactive.0)
+            .map_or(0, |(input, _, _)| input.0 + 1);
+        focus(&mut inputs, focused % CHANNELS.len());
+    }
+
+    let typed: Vec<KeyCode> = keys
+        .get_pressed()
+        .chain(keys.get_just_released())
+        .filter(|key| !MODIFIERS.contains(key))
+        .copied()
+        .collect();
+    for key in typed {
+        keys.reset(key);
+    }
+}
+
+fn focus(
+    inputs: &mut Query<(&FormulaInput, &mut TextInputInactive, &mut BorderColor)>,
+    channel: usize,
+) {
+    for (input, mut inactive, mut border_color) in inputs.iter_mut() {
+        inactive.0 = input.0 != channel;
+        *border_color = match inactive.0 {
+            true => BORDER_COLOR_INACTIVE.into(),
+            false => BORDER_COLOR_ACTIVE.into(),
+        };
+    }
+}
+
+fn focus_on_click(
+    clicked: Query<(&FormulaInput, &Interaction), Changed<Interaction>>,
+    mut inputs: Query<(&FormulaInput, &mut TextInputInactive, &mut BorderColor)>,
+) {
+    for (input, interaction) in clicked.iter() {
+        if *interaction == Interaction::Pressed {
+            focus(&mut inputs, input.0);
+        }
+    }
+}
+
+// Filled with the formulas of the artwork on screen
+fn open_editor(
+    mut commands: Commands,
+    editors: Query<(), With<FormulaEditor>>,
+    artwork: Option<Res<Artwork>>,
+) {
+    if !editors.is_empty() {
+        return;
+    }
+
+    let formulas = match artwork {
+        Some(artwork) => [&artwork.r, &artwork.g, &artwork.b].map(to_formula),
+        None => default(),
+    };
+    let text_font = TextFont {
+        font_size: FONT_SIZE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(0.0),
+                width: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(5.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..default()
+            },
+            BackgroundColor(BACKGROUND_COLOR),
+            FormulaEditor,
+        ))
+        .with_children(|parent| {
+            for (channel, formula) in formulas.into_iter().enumerate() {
+                parent
+                    .spawn(Node {
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(10.0),
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        row.spawn((
+                            Text::new(CHANNELS[channel]),
+                            text_font.clone(),
+                            TextColor(TEXT_COLOR),
+                        ));
+                        row.spawn((
+                            Node {
+                                flex_grow: 1.0,
+                                border: UiRect::all(Val::Px(3.0)),
+                                padding: UiRect::all(Val::Px(5.0)),
+                                overflow: Overflow::clip(),
+                                ..default()
+                            },
+                            BorderColor(match channel {
+                                0 => BORDER_COLOR_ACTIVE,
+                                _ => BORDER_COLOR_INACTIVE,
+                            }),
+                            FocusPolicy::Block,
+                            TextInput,
+                            TextInputValue(formula),
+                            TextInputSettings {
+                                retain_on_submit: true,
+                                ..default()
+                            },
+                            TextInputInactive(channel != 0),
+                            TextInputTextFont(text_font.clone()),
+                            TextInputTextColor(TextColor(TEXT_COLOR)),
+                            FormulaInput(channel),
+                        ));
+                    });
+                parent.spawn((
+                    Text::default(),
+                    text_font.clone(),
+                    TextColor(ERROR_COLOR),
+                    FormulaErrorText(channel),
+                ));
+            }
+        });
+}
+
+// Pressing enter in any input renders all three formulas, as long as they
+// all parse
+fn apply_formulas(
+    mut commands: Commands,
+    mut events: EventReader<TextInputSubmitEvent>,
+    inputs: Query<(&FormulaInput, &TextInputValue)>,
+    mut error_texts: Query<(&FormulaErrorText, &mut Text)>,
+    mut source: ResMut<ArtworkSource>,
+) {
+    let submitted = events
+        .read()
+        .fold(false, |found, event| found | inputs.contains(event.entity));
+    if !submitted {
+        return;
+    }
+
+    let mut trees: [Option<NodeKind>; 3] = default();
+    for (input, value) in inputs.iter() {
+        let result = value.0.parse::<NodeKind>();
+        for (error_text, mut text) in error_texts.iter_mut() {
+            if error_text.0 == input.0 {
+                text.0 = match &result {
+                    Ok(_) => String::new(),
+                    Err(error) => format!("{}", error),
+                };
+            }
+        }
+        trees[input.0] = result.ok();
+    }
+
+    if let [Some(r), Some(g), Some(b)] = trees {
+        commands.insert_resource(Artwork {
+            seed: None,
+            r,
+            g,
+            b,
+        });
+        *source = ArtworkSource::Loaded;
+    }
+}
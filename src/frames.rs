@@ -0,0 +1,185 @@
+use std::{
+    f32::consts::TAU,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    animation::AnimationTime,
+    artwork::Artwork,
+    render::{generate_image, render_pixels, CpuBackend},
+    screenshot::ImageMetadata,
+    supersampling::Supersampling,
+};
+
+/// Frame rate of a 2 centisecond delay, the shortest gif viewers respect
+const MAX_GIF_FPS: u32 = 50;
+
+/// An animation of one period of the periodic terminals (`time`, `sin_t`,
+/// `saw`), so the exported frames loop seamlessly. The raw `t` terminal
+/// never repeats, so it's wrapped like with `AnimationTime::looping` and
+/// jumps back once per loop
+#[derive(Debug, Clone, Copy)]
+pub struct FrameExport {
+    pub frames: u32,
+    pub fps: u32,
+}
+
+impl FrameExport {
+    /// `t` of each frame, starting at `start` and wrapped into `0..TAU`. The
+    /// last frame stops one step short of the period, which the first frame
+    /// then completes
+    pub fn times(&self, start: f32) -> impl Iterator<Item = f32> {
+        let frames = self.frames;
+        let animation = AnimationTime {
+            period: TAU,
+            looping: true,
+        };
+        (0..frames).map(move |i| animation.at(start + TAU * i as f32 / frames as f32))
+    }
+
+    /// Render every frame on the CPU and write them as a `.gif`, an animated
+    /// `.png` or, when `path` has no extension, a directory of numbered PNGs
+    /// each carrying its own `metadata`
+    pub fn save(
+        &self,
+        artwork: &Artwork,
+        metadata: &ImageMetadata,
+        (width, height): (u32, u32),
+        supersampling: Supersampling,
+        path: &Path,
+    ) -> Result<(), String> {
+        let frames = self.times(metadata.time).map(|time| {
+            let mut image = generate_image(width, height);
+            render_pixels(
+                &mut image,
+                artwork,
+                time,
+                CpuBackend::default(),
+                supersampling,
+            );
+            (time, image)
+        });
+        let write_error = |error: &dyn std::fmt::Display| {
+            format!("could not write {}: {}", path.display(), error)
+        };
+
+        match FrameFormat::of(path)? {
+            FrameFormat::Gif => {
+                // Delays are whole centiseconds and viewers replace ones
+                // under 2 with their own
+                if self.fps > MAX_GIF_FPS {
+                    return Err(format!(
+                        "gifs can't play faster than {} fps, use .png or a directory",
+                        MAX_GIF_FPS
+                    ));
+                }
+                let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+                    (Ok(width), Ok(height)) => (width, height),
+                    _ => return Err("gifs can't be larger than 65535x65535".to_string()),
+                };
+                let file = File::create(path).map_err(|error| write_error(&error))?;
+                let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &[])
+                    .map_err(|error| write_error(&error))?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(|error| write_error(&error))?;
+
+                for (_, image) in frames {
+                    let rgb = rgb_pixels(image)?;
+                    // Palettes are picked per frame, 10 is the encoder's
+                    // default tradeoff between quality and speed
+                    let mut frame = gif::Frame::from_rgb_speed(width, height, &rgb, 10);
+                    frame.delay = (100. / self.fps as f32).round() as u16;
+                    encoder
+                        .write_frame(&frame)
+                        .map_err(|error| write_error(&error))?;
+                }
+                Ok(())
+            }
+            FrameFormat::Apng => {
+                let file = File::create(path).map_err(|error| write_error(&error))?;
+                let mut encoder = metadata.png_encoder(BufWriter::new(file), width, height)?;
+                encoder
+                    .set_animated(self.frames, 0)
+                    .and_then(|()| encoder.set_frame_delay(1, self.fps as u16))
+                    .map_err(|error| write_error(&error))?;
+                let mut writer = encoder
+                    .write_header()
+                    .map_err(|error| write_error(&error))?;
+
+                for (_, image) in frames {
+                    writer
+                        .write_image_data(&rgb_pixels(image)?)
+                        .map_err(|error| write_error(&error))?;
+                }
+                writer.finish().map_err(|error| write_error(&error))
+            }
+            FrameFormat::Sequence => {
+                std::fs::create_dir_all(path).map_err(|error| write_error(&error))?;
+                for (i, (time, image)) in frames.enumerate() {
+                    let metadata = ImageMetadata {
+                        time,
+                        ..metadata.clone()
+                    };
+                    metadata.save_png(&image, &frame_path(path, i))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Path of the `i`th frame of a numbered sequence in `directory`
+fn frame_path(directory: &Path, i: usize) -> PathBuf {
+    directory.join(format!("frame-{:04}.png", i))
+}
+
+fn rgb_pixels(image: bevy::prelude::Image) -> Result<Vec<u8>, String> {
+    Ok(image
+        .try_into_dynamic()
+        .map_err(|error| error.to_string())?
+        .to_rgb8()
+        .into_raw())
+}
+
+enum FrameFormat {
+    Gif,
+    Apng,
+    Sequence,
+}
+
+impl FrameFormat {
+    fn of(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gif") => Ok(FrameFormat::Gif),
+            Some("png") => Ok(FrameFormat::Apng),
+            None => Ok(FrameFormat::Sequence),
+            _ => Err(format!(
+                "{} should end in .gif or .png, or be a directory",
+                path.display()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_wrap_into_one_period() {
+        let export = FrameExport { frames: 4, fps: 10 };
+        for start in [TAU + 1., -3.] {
+            let times: Vec<f32> = export.times(start).collect();
+            assert_eq!(times.len(), 4);
+            assert!(times.iter().all(|t| (0. ..TAU).contains(t)), "{:?}", times);
+            // The periodic terminals still see a full period
+            for (i, t) in times.iter().enumerate() {
+                let expected = (start + TAU * i as f32 / 4.).sin();
+                assert!((t.sin() - expected).abs() < 1e-5, "{:?}", times);
+            }
+        }
+    }
+}
@@ -1,26 +1,53 @@
-use std::fmt::{write, Display};
+use std::{
+    f32::consts::TAU,
+    fmt::{write, Display},
+};
 
-use rand::{rngs::StdRng, Rng};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-use crate::state::RenderState;
+use crate::{
+    grammar::{Grammar, Op},
+    state::RenderState,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NodeBinop {
-    lhs: Box<NodeKind>,
-    rhs: Box<NodeKind>,
+    pub lhs: Box<NodeKind>,
+    pub rhs: Box<NodeKind>,
 }
 
-#[derive(Debug, Clone)]
+impl NodeBinop {
+    pub fn new(lhs: Box<NodeKind>, rhs: Box<NodeKind>) -> Self {
+        NodeBinop { lhs, rhs }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NodeUnop {
-    value: Box<NodeKind>,
+    pub value: Box<NodeKind>,
 }
 
-enum NodeState {
-    A,
-    C,
+impl NodeUnop {
+    pub fn new(value: Box<NodeKind>) -> Self {
+        NodeUnop { value }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeTernop {
+    pub a: Box<NodeKind>,
+    pub b: Box<NodeKind>,
+    pub c: Box<NodeKind>,
+}
+
+impl NodeTernop {
+    pub fn new(a: Box<NodeKind>, b: Box<NodeKind>, c: Box<NodeKind>) -> Self {
+        NodeTernop { a, b, c }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum NodeKind {
     X,
     Y,
@@ -33,6 +60,171 @@ pub enum NodeKind {
     Mod(NodeBinop),
     Gt(NodeBinop),
     Time,
+    Sub(NodeBinop),
+    Div(NodeBinop),
+    Cos(NodeUnop),
+    Tan(NodeUnop),
+    Exp(NodeUnop),
+    Log(NodeUnop),
+    Pow(NodeBinop),
+    Min(NodeBinop),
+    Max(NodeBinop),
+    Atan2(NodeBinop),
+    Floor(NodeUnop),
+    Fract(NodeUnop),
+    Mix(NodeTernop),
+    Smoothstep(NodeTernop),
+    Clamp(NodeTernop),
+    Lt(NodeBinop),
+    If(NodeTernop),
+    Radius,
+    Angle,
+    DistTo(f32, f32),
+    T,
+    SinT(f32),
+    Saw,
+}
+
+impl NodeKind {
+    pub fn children(&self) -> Vec<&NodeKind> {
+        match self {
+            NodeKind::X
+            | NodeKind::Y
+            | NodeKind::Random(_)
+            | NodeKind::Time
+            | NodeKind::Radius
+            | NodeKind::Angle
+            | NodeKind::DistTo(_, _)
+            | NodeKind::T
+            | NodeKind::SinT(_)
+            | NodeKind::Saw => vec![],
+            NodeKind::Sqrt(node_unop)
+            | NodeKind::Abs(node_unop)
+            | NodeKind::Sin(node_unop)
+            | NodeKind::Cos(node_unop)
+            | NodeKind::Tan(node_unop)
+            | NodeKind::Exp(node_unop)
+            | NodeKind::Log(node_unop)
+            | NodeKind::Floor(node_unop)
+            | NodeKind::Fract(node_unop) => vec![&node_unop.value],
+            NodeKind::Add(node_binop)
+            | NodeKind::Mult(node_binop)
+            | NodeKind::Mod(node_binop)
+            | NodeKind::Gt(node_binop)
+            | NodeKind::Sub(node_binop)
+            | NodeKind::Div(node_binop)
+            | NodeKind::Pow(node_binop)
+            | NodeKind::Min(node_binop)
+            | NodeKind::Max(node_binop)
+            | NodeKind::Atan2(node_binop)
+            | NodeKind::Lt(node_binop) => vec![&node_binop.lhs, &node_binop.rhs],
+            NodeKind::Mix(node_ternop)
+            | NodeKind::Smoothstep(node_ternop)
+            | NodeKind::Clamp(node_ternop)
+            | NodeKind::If(node_ternop) => vec![&node_ternop.a, &node_ternop.b, &node_ternop.c],
+        }
+    }
+
+    pub fn op(&self) -> Op {
+        match self {
+            NodeKind::X => Op::X,
+            NodeKind::Y => Op::Y,
+            NodeKind::Random(_) => Op::Random,
+            NodeKind::Add(_) => Op::Add,
+            NodeKind::Mult(_) => Op::Mult,
+            NodeKind::Sqrt(_) => Op::Sqrt,
+            NodeKind::Abs(_) => Op::Abs,
+            NodeKind::Sin(_) => Op::Sin,
+            NodeKind::Mod(_) => Op::Mod,
+            NodeKind::Gt(_) => Op::Gt,
+            NodeKind::Time => Op::Time,
+            NodeKind::Sub(_) => Op::Sub,
+            NodeKind::Div(_) => Op::Div,
+            NodeKind::Cos(_) => Op::Cos,
+            NodeKind::Tan(_) => Op::Tan,
+            NodeKind::Exp(_) => Op::Exp,
+            NodeKind::Log(_) => Op::Log,
+            NodeKind::Pow(_) => Op::Pow,
+            NodeKind::Min(_) => Op::Min,
+            NodeKind::Max(_) => Op::Max,
+            NodeKind::Atan2(_) => Op::Atan2,
+            NodeKind::Floor(_) => Op::Floor,
+            NodeKind::Fract(_) => Op::Fract,
+            NodeKind::Mix(_) => Op::Mix,
+            NodeKind::Smoothstep(_) => Op::Smoothstep,
+            NodeKind::Clamp(_) => Op::Clamp,
+            NodeKind::Lt(_) => Op::Lt,
+            NodeKind::If(_) => Op::If,
+            NodeKind::Radius => Op::Radius,
+            NodeKind::Angle => Op::Angle,
+            NodeKind::DistTo(_, _) => Op::DistTo,
+            NodeKind::T => Op::T,
+            NodeKind::SinT(_) => Op::SinT,
+            NodeKind::Saw => Op::Saw,
+        }
+    }
+
+    /// Copy of the node with `f` applied to each of its children
+    pub fn map_children(&self, f: impl FnMut(&NodeKind) -> NodeKind) -> NodeKind {
+        let children = self.children().into_iter().map(f).collect();
+        // Only the leaves holding constants can't be rebuilt from their op
+        self.op().apply(children).unwrap_or_else(|| self.clone())
+    }
+}
+
+/// Depth of the generated r, g and b trees
+pub const MAX_DEPTH: u32 = 30;
+
+/// Smallest magnitude used as a divisor or log/pow base, so both renderers
+/// stay finite in the same places
+const EPSILON: f32 = 1e-6;
+
+/// WGSL versions of the guarded functions used by `eval`, the generated
+/// shader code calls these so both renderers agree
+pub const SHADER_FUNCTIONS: &str = "
+        fn safe_div(a: f32, b: f32) -> f32 {
+            return select(a / b, 0.0, abs(b) < 1e-6);
+        }
+
+        fn safe_log(v: f32) -> f32 {
+            return log(max(abs(v), 1e-6));
+        }
+
+        fn safe_pow(a: f32, b: f32) -> f32 {
+            return pow(max(abs(a), 1e-6), b);
+        }
+
+        fn safe_smoothstep(low: f32, high: f32, v: f32) -> f32 {
+            let t = clamp(safe_div(v - low, high - low), 0.0, 1.0);
+            return t * t * (3.0 - 2.0 * t);
+        }
+";
+
+pub fn safe_div(a: f32, b: f32) -> f32 {
+    if b.abs() < EPSILON {
+        0.
+    } else {
+        a / b
+    }
+}
+
+pub fn safe_log(v: f32) -> f32 {
+    v.abs().max(EPSILON).ln()
+}
+
+pub fn safe_pow(a: f32, b: f32) -> f32 {
+    a.abs().max(EPSILON).powf(b)
+}
+
+pub fn safe_smoothstep(low: f32, high: f32, v: f32) -> f32 {
+    let t = safe_div(v - low, high - low).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// Sawtooth going from -1 to 1 once every TAU of `t`
+pub fn saw(t: f32) -> f32 {
+    let phase = t / TAU;
+    (phase - phase.floor()) * 2. - 1.
 }
 
 impl Display for NodeKind {
@@ -44,51 +236,161 @@ impl Display for NodeKind {
 }
 
 pub fn generate_shader_code(node: &NodeKind) -> String {
+    shader_code_with(node, &mut generate_shader_code)
+}
+
+/// WGSL for `node` with the code of its children produced by `child`
+pub fn shader_code_with(node: &NodeKind, child: &mut impl FnMut(&NodeKind) -> String) -> String {
     match node {
         NodeKind::X => "mesh.uv.x * 2.0 - 1.0".to_string(),
         NodeKind::Y => "mesh.uv.y * 2.0 - 1.0".to_string(),
-        NodeKind::Random(r) => format!("f32({})", *r),
+        NodeKind::Random(r) => format!("f32({:?})", *r),
         NodeKind::Add(node_binop) => {
             format!(
                 "({}) + ({})",
-                generate_shader_code(node_binop.lhs.as_ref()),
-                generate_shader_code(node_binop.rhs.as_ref())
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
             )
         }
         NodeKind::Mult(node_binop) => {
             format!(
                 "({}) * ({})",
-                generate_shader_code(node_binop.lhs.as_ref()),
-                generate_shader_code(node_binop.rhs.as_ref())
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
             )
         }
         NodeKind::Sqrt(node_unop) => {
-            format!(
-                "sqrt(abs({}))",
-                generate_shader_code(node_unop.value.as_ref())
-            )
+            format!("sqrt(abs({}))", child(node_unop.value.as_ref()))
         }
         NodeKind::Abs(node_unop) => {
-            format!("abs({})", generate_shader_code(node_unop.value.as_ref()))
+            format!("abs({})", child(node_unop.value.as_ref()))
         }
         NodeKind::Sin(node_unop) => {
-            format!("sin({})", generate_shader_code(node_unop.value.as_ref()))
+            format!("sin({})", child(node_unop.value.as_ref()))
         }
         NodeKind::Mod(node_binop) => {
             format!(
                 "({}) % ({})",
-                generate_shader_code(node_binop.lhs.as_ref()),
-                generate_shader_code(node_binop.rhs.as_ref())
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
             )
         }
         NodeKind::Gt(node_binop) => {
             format!(
                 "f32(({}) > ({}))",
-                generate_shader_code(node_binop.lhs.as_ref()),
-                generate_shader_code(node_binop.rhs.as_ref())
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
+            )
+        }
+        NodeKind::Time => "sin(t)".to_string(),
+        NodeKind::Sub(node_binop) => {
+            format!(
+                "({}) - ({})",
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
+            )
+        }
+        NodeKind::Div(node_binop) => {
+            format!(
+                "safe_div({}, {})",
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
+            )
+        }
+        NodeKind::Cos(node_unop) => {
+            format!("cos({})", child(node_unop.value.as_ref()))
+        }
+        NodeKind::Tan(node_unop) => {
+            format!("tan({})", child(node_unop.value.as_ref()))
+        }
+        NodeKind::Exp(node_unop) => {
+            format!("exp({})", child(node_unop.value.as_ref()))
+        }
+        NodeKind::Log(node_unop) => {
+            format!("safe_log({})", child(node_unop.value.as_ref()))
+        }
+        NodeKind::Pow(node_binop) => {
+            format!(
+                "safe_pow({}, {})",
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
+            )
+        }
+        NodeKind::Min(node_binop) => {
+            format!(
+                "min({}, {})",
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
+            )
+        }
+        NodeKind::Max(node_binop) => {
+            format!(
+                "max({}, {})",
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
+            )
+        }
+        NodeKind::Atan2(node_binop) => {
+            format!(
+                "atan2({}, {})",
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
             )
         }
-        NodeKind::Time => format!("sin(globals.time)"),
+        NodeKind::Floor(node_unop) => {
+            format!("floor({})", child(node_unop.value.as_ref()))
+        }
+        NodeKind::Fract(node_unop) => {
+            format!("fract({})", child(node_unop.value.as_ref()))
+        }
+        NodeKind::Mix(node_ternop) => {
+            format!(
+                "mix({}, {}, {})",
+                child(node_ternop.a.as_ref()),
+                child(node_ternop.b.as_ref()),
+                child(node_ternop.c.as_ref())
+            )
+        }
+        NodeKind::Smoothstep(node_ternop) => {
+            format!(
+                "safe_smoothstep({}, {}, {})",
+                child(node_ternop.a.as_ref()),
+                child(node_ternop.b.as_ref()),
+                child(node_ternop.c.as_ref())
+            )
+        }
+        NodeKind::Clamp(node_ternop) => {
+            // Spelled out since WGSL's clamp requires low <= high
+            format!(
+                "min(max({}, {}), {})",
+                child(node_ternop.a.as_ref()),
+                child(node_ternop.b.as_ref()),
+                child(node_ternop.c.as_ref())
+            )
+        }
+        NodeKind::Lt(node_binop) => {
+            format!(
+                "f32(({}) < ({}))",
+                child(node_binop.lhs.as_ref()),
+                child(node_binop.rhs.as_ref())
+            )
+        }
+        NodeKind::If(node_ternop) => {
+            format!(
+                "select({}, {}, ({}) > 0.0)",
+                child(node_ternop.c.as_ref()),
+                child(node_ternop.b.as_ref()),
+                child(node_ternop.a.as_ref())
+            )
+        }
+        NodeKind::Radius => "length(mesh.uv * 2.0 - 1.0)".to_string(),
+        NodeKind::Angle => "atan2(mesh.uv.y * 2.0 - 1.0, mesh.uv.x * 2.0 - 1.0)".to_string(),
+        NodeKind::DistTo(px, py) => {
+            format!("distance(mesh.uv * 2.0 - 1.0, vec2f({:?}, {:?}))", *px, *py)
+        }
+        NodeKind::T => "t".to_string(),
+        NodeKind::SinT(freq) => format!("sin(t * f32({:?}))", *freq),
+        NodeKind::Saw => format!("fract(t / f32({})) * 2.0 - 1.0", TAU),
     }
 }
 
@@ -114,56 +416,133 @@ pub fn eval(x: f32, y: f32, node: &NodeKind, time: f32) -> f32 {
                 as i32 as f32
         }
         NodeKind::Time => time.sin(),
+        NodeKind::Sub(node_binop) => {
+            eval(x, y, node_binop.lhs.as_ref(), time) - eval(x, y, node_binop.rhs.as_ref(), time)
+        }
+        NodeKind::Div(node_binop) => safe_div(
+            eval(x, y, node_binop.lhs.as_ref(), time),
+            eval(x, y, node_binop.rhs.as_ref(), time),
+        ),
+        NodeKind::Cos(node_unop) => eval(x, y, node_unop.value.as_ref(), time).cos(),
+        NodeKind::Tan(node_unop) => eval(x, y, node_unop.value.as_ref(), time).tan(),
+        NodeKind::Exp(node_unop) => eval(x, y, node_unop.value.as_ref(), time).exp(),
+        NodeKind::Log(node_unop) => safe_log(eval(x, y, node_unop.value.as_ref(), time)),
+        NodeKind::Pow(node_binop) => safe_pow(
+            eval(x, y, node_binop.lhs.as_ref(), time),
+            eval(x, y, node_binop.rhs.as_ref(), time),
+        ),
+        NodeKind::Min(node_binop) => {
+            eval(x, y, node_binop.lhs.as_ref(), time).min(eval(x, y, node_binop.rhs.as_ref(), time))
+        }
+        NodeKind::Max(node_binop) => {
+            eval(x, y, node_binop.lhs.as_ref(), time).max(eval(x, y, node_binop.rhs.as_ref(), time))
+        }
+        NodeKind::Atan2(node_binop) => eval(x, y, node_binop.lhs.as_ref(), time).atan2(eval(
+            x,
+            y,
+            node_binop.rhs.as_ref(),
+            time,
+        )),
+        NodeKind::Floor(node_unop) => eval(x, y, node_unop.value.as_ref(), time).floor(),
+        NodeKind::Fract(node_unop) => {
+            // WGSL's fract, f32::fract truncates towards zero instead
+            let v = eval(x, y, node_unop.value.as_ref(), time);
+            v - v.floor()
+        }
+        NodeKind::Mix(node_ternop) => {
+            let t = eval(x, y, node_ternop.c.as_ref(), time);
+            eval(x, y, node_ternop.a.as_ref(), time) * (1. - t)
+                + eval(x, y, node_ternop.b.as_ref(), time) * t
+        }
+        NodeKind::Smoothstep(node_ternop) => safe_smoothstep(
+            eval(x, y, node_ternop.a.as_ref(), time),
+            eval(x, y, node_ternop.b.as_ref(), time),
+            eval(x, y, node_ternop.c.as_ref(), time),
+        ),
+        NodeKind::Clamp(node_ternop) => eval(x, y, node_ternop.a.as_ref(), time)
+            .max(eval(x, y, node_ternop.b.as_ref(), time))
+            .min(eval(x, y, node_ternop.c.as_ref(), time)),
+        NodeKind::Lt(node_binop) => {
+            (eval(x, y, node_binop.lhs.as_ref(), time) < eval(x, y, node_binop.rhs.as_ref(), time))
+                as i32 as f32
+        }
+        NodeKind::If(node_ternop) => {
+            if eval(x, y, node_ternop.a.as_ref(), time) > 0. {
+                eval(x, y, node_ternop.b.as_ref(), time)
+            } else {
+                eval(x, y, node_ternop.c.as_ref(), time)
+            }
+        }
+        NodeKind::Radius => (x * x + y * y).sqrt(),
+        NodeKind::Angle => y.atan2(x),
+        NodeKind::DistTo(px, py) => ((x - px) * (x - px) + (y - py) * (y - py)).sqrt(),
+        NodeKind::T => time,
+        NodeKind::SinT(freq) => (time * freq).sin(),
+        NodeKind::Saw => saw(time),
     }
 }
 
-pub fn generate_tree(depth: u32, rng: &mut StdRng) -> NodeKind {
-    let state = match depth == 0 {
-        true => NodeState::A,
-        false => {
-            if rng.gen_bool(1f64 / 4f64) {
-                NodeState::A
-            } else {
-                NodeState::C
+pub fn generate_tree(grammar: &Grammar, depth: u32, rng: &mut StdRng) -> NodeKind {
+    grammar.expand(&grammar.entry, depth, rng)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::grammar::Op;
+
+    /// Trees of the extended grammar for a few seeds, plus `if`s whose
+    /// conditions are NaN at some points, to compare the evaluators with
+    pub fn sample_trees() -> Vec<NodeKind> {
+        let grammar: Grammar = include_str!("../assets/grammars/extended.grammar")
+            .parse()
+            .unwrap();
+        let mut trees: Vec<NodeKind> = (0..40)
+            .map(|seed| generate_tree(&grammar, 10, &mut StdRng::seed_from_u64(seed)))
+            .collect();
+
+        let call = |op: Op, args: Vec<NodeKind>| op.apply(args).unwrap();
+        // NaN everywhere, NaN at `x == 0` and either branch elsewhere, and
+        // branches splitting every row
+        let conditions = [
+            call(Op::Mod, vec![NodeKind::X, NodeKind::Random(0.)]),
+            call(Op::Mod, vec![NodeKind::Random(1.), NodeKind::X]),
+            call(Op::Sub, vec![NodeKind::X, NodeKind::Random(0.25)]),
+        ];
+        for condition in conditions {
+            trees.push(call(
+                Op::If,
+                vec![
+                    condition,
+                    call(Op::Sin, vec![NodeKind::X]),
+                    call(Op::Add, vec![NodeKind::Y, NodeKind::Random(-0.5)]),
+                ],
+            ));
+        }
+        trees
+    }
+
+    /// Check that `evaluate` gives the values of `eval` for `tree` at points
+    /// covering the image, including the axes, and at a few times. NaNs
+    /// count as equal, and so do -0 and 0 since they draw the same
+    pub fn assert_matches_eval(tree: &NodeKind, evaluate: impl Fn(f32, f32, f32) -> f32) {
+        let coordinates = (0..=8).map(|i| i as f32 / 4. - 1.);
+        for x in coordinates.clone() {
+            for y in coordinates.clone() {
+                for time in [0., 1.3, 5.] {
+                    let (expected, actual) = (eval(x, y, tree, time), evaluate(x, y, time));
+                    assert!(
+                        expected == actual || (expected.is_nan() && actual.is_nan()),
+                        "{:?} at {:?}: {} != {}",
+                        tree,
+                        (x, y, time),
+                        expected,
+                        actual
+                    );
+                }
             }
         }
-    };
-
-    match state {
-        NodeState::A => match rng.gen_range(1..=4) {
-            1 => NodeKind::X,
-            2 => NodeKind::Y,
-            3 => NodeKind::Random(rng.gen_range(-1f32..=1f32)),
-            4 => NodeKind::Time,
-            _ => unreachable!(),
-        },
-        NodeState::C => match rng.gen_range(1..=7) {
-            1 => NodeKind::Add(NodeBinop {
-                lhs: Box::new(generate_tree(depth - 1, rng)),
-                rhs: Box::new(generate_tree(depth - 1, rng)),
-            }),
-            2 => NodeKind::Mult(NodeBinop {
-                lhs: Box::new(generate_tree(depth - 1, rng)),
-                rhs: Box::new(generate_tree(depth - 1, rng)),
-            }),
-            3 => NodeKind::Sqrt(NodeUnop {
-                value: Box::new(generate_tree(depth - 1, rng)),
-            }),
-            4 => NodeKind::Abs(NodeUnop {
-                value: Box::new(generate_tree(depth - 1, rng)),
-            }),
-            5 => NodeKind::Sin(NodeUnop {
-                value: Box::new(generate_tree(depth - 1, rng)),
-            }),
-            6 => NodeKind::Mod(NodeBinop {
-                lhs: Box::new(generate_tree(depth - 1, rng)),
-                rhs: Box::new(generate_tree(depth - 1, rng)),
-            }),
-            7 => NodeKind::Gt(NodeBinop {
-                lhs: Box::new(generate_tree(depth - 1, rng)),
-                rhs: Box::new(generate_tree(depth - 1, rng)),
-            }),
-            _ => unreachable!(),
-        },
     }
 }
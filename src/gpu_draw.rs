@@ -5,18 +5,33 @@ use bevy::{
     sprite::{Material2d, Material2dPlugin},
     window::WindowResized,
 };
-use rand::SeedableRng;
 
-use crate::{generate_tree, render::generate_image, seed::Seed, state::RenderState};
+use crate::{
+    animation::AnimationTime,
+    artwork::Artwork,
+    clock::RenderClock,
+    func_gen::SHADER_FUNCTIONS,
+    optimize::shared_shader_code,
+    render::generate_image,
+    state::RenderState,
+    supersampling::{Supersampling, JITTER_FUNCTION},
+};
 
 pub const MESH2D_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(6942000000000);
 
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 struct CustomMaterial {
+    /// `t` of the `RenderClock` in `x`, the same value the CPU renderer
+    /// uses. Padded to a vector since WebGL2 needs 16 byte uniforms
     #[uniform(0)]
-    color: LinearRgba,
+    clock: Vec4,
 }
 
+/// WGSL binding of `CustomMaterial::clock`
+const CLOCK_UNIFORM: &str = "
+@group(2) @binding(0) var<uniform> clock: vec4<f32>;
+";
+
 impl Material2d for CustomMaterial {
     fn fragment_shader() -> ShaderRef {
         MESH2D_SHADER_HANDLE.into()
@@ -31,34 +46,37 @@ impl Plugin for GpuRenderPlugin {
         shaders.insert(
             &MESH2D_SHADER_HANDLE,
             Shader::from_wgsl(
-                r#"
-            #import bevy_sprite::{
-                mesh2d_vertex_output::VertexOutput,
-                mesh2d_view_bindings::globals,
-                }
-
-            @group(2) @binding(0) var base_color_texture: texture_2d<f32>;
-
+                format!(
+                    r#"
+            #import bevy_sprite::mesh2d_vertex_output::VertexOutput
+            {}
             @fragment
-            fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
-                return vec4f((sin(f32(-0.04351914))) % (f32((f32(-0.74157834)) > (f32(-0.02816999)))), sin(globals.time), sin(globals.time), 1.0);
-            }
+            fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {{
+                return vec4f((sin(f32(-0.04351914))) % (f32((f32(-0.74157834)) > (f32(-0.02816999)))), sin(clock.x), sin(clock.x), 1.0);
+            }}
             "#,
+                    CLOCK_UNIFORM,
+                ),
                 file!(),
             ),
         );
 
         app.add_plugins(Material2dPlugin::<CustomMaterial>::default())
-            .add_systems(Update, gpu_draw.run_if(should_run));
+            .add_systems(Update, gpu_draw.run_if(should_run))
+            .add_systems(Update, update_time.after(gpu_draw));
     }
 }
 
 fn should_run(
     mut resize_reader: EventReader<WindowResized>,
-    seed: Res<Seed>,
+    artwork: Res<Artwork>,
+    supersampling: Res<Supersampling>,
     state: Res<State<RenderState>>,
 ) -> bool {
-    (resize_reader.read().last().is_some() | seed.is_changed() | state.is_changed())
+    (resize_reader.read().last().is_some()
+        | artwork.is_changed()
+        | supersampling.is_changed()
+        | state.is_changed())
         & (*state.get() == RenderState::GpuRender)
 }
 
@@ -69,40 +87,45 @@ fn gpu_draw(
     mesh_entities: Query<Entity, With<Mesh2d>>,
     windows: Query<&Window>,
     mut shaders: ResMut<Assets<Shader>>,
-    seed: ResMut<Seed>,
+    artwork: Res<Artwork>,
+    clock: Res<RenderClock>,
+    animation: Res<AnimationTime>,
+    supersampling: Res<Supersampling>,
 ) {
     let window = windows.single();
 
-    const MAX_DEPTH: u32 = 30;
-    let mut rng = rand::rngs::StdRng::seed_from_u64(seed.0);
-
-    info!("{}", seed.0);
-
-    let r_tree = generate_tree(MAX_DEPTH, &mut rng);
-    // info!("{:?}", r_tree);
-    let g_tree = generate_tree(MAX_DEPTH, &mut rng);
-    // info!("{:?}", g_tree);
-    let b_tree = generate_tree(MAX_DEPTH, &mut rng);
-    // info!("{:?}", b_tree);
+    let artwork = artwork.simplified();
+    let (shared, [r, g, b]) = shared_shader_code([&artwork.r, &artwork.g, &artwork.b]);
 
     shaders.insert(
         &MESH2D_SHADER_HANDLE,
         Shader::from_wgsl(
             format!(
                 "
-        #import bevy_sprite::{{
-            mesh2d_vertex_output::VertexOutput,
-            mesh2d_view_bindings::globals,
-            }}
-
-        @group(2) @binding(0) var base_color_texture: texture_2d<f32>;
+        #import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+        {}
+        {}
+        {}
+        fn color(mesh: VertexOutput, t: f32) -> vec3<f32> {{
+            {}
+            return vec3f(({}), ({}), ({}));
+        }}
 
         @fragment
         fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {{
-            return vec4f(({}), ({}), ({}), (1.0));
+            let t = clock.x;
+            {}
         }}
         ",
-                r_tree, g_tree, b_tree
+                CLOCK_UNIFORM,
+                SHADER_FUNCTIONS,
+                JITTER_FUNCTION,
+                shared,
+                r,
+                g,
+                b,
+                supersampling.shader_code(),
             ),
             file!(),
         ),
@@ -118,7 +141,24 @@ fn gpu_draw(
             window.resolution.height(),
         ))),
         MeshMaterial2d(materials.add(CustomMaterial {
-            color: LinearRgba::BLUE,
+            clock: Vec4::new(animation.at(clock.seconds), 0., 0., 0.),
         })),
     ));
 }
+
+fn update_time(
+    mesh_materials: Query<&MeshMaterial2d<CustomMaterial>>,
+    mut materials: ResMut<Assets<CustomMaterial>>,
+    clock: Res<RenderClock>,
+    animation: Res<AnimationTime>,
+) {
+    if !clock.is_changed() && !animation.is_changed() {
+        return;
+    }
+
+    for mesh_material in &mesh_materials {
+        if let Some(material) = materials.get_mut(&mesh_material.0) {
+            material.clock.x = animation.at(clock.seconds);
+        }
+    }
+}
@@ -0,0 +1,1032 @@
+use std::{collections::HashMap, error::Error, fmt::Display, str::FromStr};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use rand::{rngs::StdRng, Rng};
+
+use crate::func_gen::{NodeBinop, NodeKind, NodeTernop, NodeUnop};
+
+/// Asset loaded when no other grammar is picked
+pub const GRAMMAR_PATH: &str = "grammars/default.grammar";
+
+pub struct GrammarPlugin {
+    /// Asset path of the `.grammar` file to load
+    pub path: String,
+}
+
+impl Plugin for GrammarPlugin {
+    fn build(&self, app: &mut App) {
+        // The built-in grammar is used until the file finishes loading
+        app.insert_resource(GrammarPath(self.path.clone()))
+            .init_resource::<Grammar>()
+            .init_asset::<GrammarAsset>()
+            .init_asset_loader::<GrammarLoader>()
+            .add_systems(Startup, load_grammar)
+            .add_systems(Update, apply_grammar);
+    }
+}
+
+#[derive(Resource)]
+struct GrammarPath(String);
+
+#[derive(Resource)]
+struct GrammarHandle(Handle<GrammarAsset>);
+
+fn load_grammar(mut commands: Commands, asset_server: Res<AssetServer>, path: Res<GrammarPath>) {
+    commands.insert_resource(GrammarHandle(asset_server.load(path.0.clone())));
+}
+
+// Swap in the loaded grammar, also runs again when the file is hot reloaded
+fn apply_grammar(
+    mut events: EventReader<AssetEvent<GrammarAsset>>,
+    handle: Res<GrammarHandle>,
+    path: Res<GrammarPath>,
+    assets: Res<Assets<GrammarAsset>>,
+    mut grammar: ResMut<Grammar>,
+) {
+    for event in events.read() {
+        if event.is_loaded_with_dependencies(&handle.0) || event.is_modified(&handle.0) {
+            if let Some(asset) = assets.get(&handle.0) {
+                info!("loaded grammar {}", path.0);
+                *grammar = asset.0.clone();
+            }
+        }
+    }
+}
+
+/// Node a production emits, with its children taken from the production's arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    X,
+    Y,
+    Random,
+    Time,
+    Add,
+    Mult,
+    Sqrt,
+    Abs,
+    Sin,
+    Mod,
+    Gt,
+    Sub,
+    Div,
+    Cos,
+    Tan,
+    Exp,
+    Log,
+    Pow,
+    Min,
+    Max,
+    Atan2,
+    Floor,
+    Fract,
+    Mix,
+    Smoothstep,
+    Clamp,
+    Lt,
+    If,
+    Radius,
+    Angle,
+    DistTo,
+    T,
+    SinT,
+    Saw,
+}
+
+impl Op {
+    pub fn arity(self) -> usize {
+        match self {
+            Op::X
+            | Op::Y
+            | Op::Random
+            | Op::Time
+            | Op::Radius
+            | Op::Angle
+            | Op::DistTo
+            | Op::T
+            | Op::SinT
+            | Op::Saw => 0,
+            Op::Sqrt
+            | Op::Abs
+            | Op::Sin
+            | Op::Cos
+            | Op::Tan
+            | Op::Exp
+            | Op::Log
+            | Op::Floor
+            | Op::Fract => 1,
+            Op::Add
+            | Op::Mult
+            | Op::Mod
+            | Op::Gt
+            | Op::Sub
+            | Op::Div
+            | Op::Pow
+            | Op::Min
+            | Op::Max
+            | Op::Atan2
+            | Op::Lt => 2,
+            Op::Mix | Op::Smoothstep | Op::Clamp | Op::If => 3,
+        }
+    }
+
+    /// Name used for the op in grammar files
+    pub fn name(self) -> &'static str {
+        match self {
+            Op::X => "x",
+            Op::Y => "y",
+            Op::Random => "random",
+            Op::Time => "time",
+            Op::Add => "add",
+            Op::Mult => "mult",
+            Op::Sqrt => "sqrt",
+            Op::Abs => "abs",
+            Op::Sin => "sin",
+            Op::Mod => "mod",
+            Op::Gt => "gt",
+            Op::Sub => "sub",
+            Op::Div => "div",
+            Op::Cos => "cos",
+            Op::Tan => "tan",
+            Op::Exp => "exp",
+            Op::Log => "log",
+            Op::Pow => "pow",
+            Op::Min => "min",
+            Op::Max => "max",
+            Op::Atan2 => "atan2",
+            Op::Floor => "floor",
+            Op::Fract => "fract",
+            Op::Mix => "mix",
+            Op::Smoothstep => "smoothstep",
+            Op::Clamp => "clamp",
+            Op::Lt => "lt",
+            Op::If => "if",
+            Op::Radius => "radius",
+            Op::Angle => "angle",
+            Op::DistTo => "dist_to",
+            Op::T => "t",
+            Op::SinT => "sin_t",
+            Op::Saw => "saw",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let op = match name {
+            "x" => Op::X,
+            "y" => Op::Y,
+            "random" => Op::Random,
+            "time" => Op::Time,
+            "add" => Op::Add,
+            "mult" => Op::Mult,
+            "sqrt" => Op::Sqrt,
+            "abs" => Op::Abs,
+            "sin" => Op::Sin,
+            "mod" => Op::Mod,
+            "gt" => Op::Gt,
+            "sub" => Op::Sub,
+            "div" => Op::Div,
+            "cos" => Op::Cos,
+            "tan" => Op::Tan,
+            "exp" => Op::Exp,
+            "log" => Op::Log,
+            "pow" => Op::Pow,
+            "min" => Op::Min,
+            "max" => Op::Max,
+            "atan2" => Op::Atan2,
+            "floor" => Op::Floor,
+            "fract" => Op::Fract,
+            "mix" => Op::Mix,
+            "smoothstep" => Op::Smoothstep,
+            "clamp" => Op::Clamp,
+            "lt" => Op::Lt,
+            "if" => Op::If,
+            "radius" => Op::Radius,
+            "angle" => Op::Angle,
+            "dist_to" => Op::DistTo,
+            "t" => Op::T,
+            "sin_t" => Op::SinT,
+            "saw" => Op::Saw,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// Build the node, `args` must hold exactly `arity` children
+    fn build(self, args: Vec<NodeKind>, rng: &mut StdRng) -> NodeKind {
+        match self {
+            Op::Random => NodeKind::Random(rng.gen_range(-1f32..=1f32)),
+            Op::DistTo => {
+                NodeKind::DistTo(rng.gen_range(-1f32..=1f32), rng.gen_range(-1f32..=1f32))
+            }
+            // Whole harmonics so the terminal repeats with the animation period
+            Op::SinT => NodeKind::SinT(rng.gen_range(1..=4) as f32),
+            _ => self.apply(args).unwrap(),
+        }
+    }
+
+    /// Build the node with `args` as its children, `None` for the ops that
+    /// hold constants instead (`random`, `dist_to` and `sin_t`)
+    pub fn apply(self, mut args: Vec<NodeKind>) -> Option<NodeKind> {
+        let mut next = || Box::new(args.remove(0));
+        let node = match self {
+            Op::X => NodeKind::X,
+            Op::Y => NodeKind::Y,
+            Op::Time => NodeKind::Time,
+            Op::Add => NodeKind::Add(NodeBinop::new(next(), next())),
+            Op::Mult => NodeKind::Mult(NodeBinop::new(next(), next())),
+            Op::Sqrt => NodeKind::Sqrt(NodeUnop::new(next())),
+            Op::Abs => NodeKind::Abs(NodeUnop::new(next())),
+            Op::Sin => NodeKind::Sin(NodeUnop::new(next())),
+            Op::Mod => NodeKind::Mod(NodeBinop::new(next(), next())),
+            Op::Gt => NodeKind::Gt(NodeBinop::new(next(), next())),
+            Op::Sub => NodeKind::Sub(NodeBinop::new(next(), next())),
+            Op::Div => NodeKind::Div(NodeBinop::new(next(), next())),
+            Op::Cos => NodeKind::Cos(NodeUnop::new(next())),
+            Op::Tan => NodeKind::Tan(NodeUnop::new(next())),
+            Op::Exp => NodeKind::Exp(NodeUnop::new(next())),
+            Op::Log => NodeKind::Log(NodeUnop::new(next())),
+            Op::Pow => NodeKind::Pow(NodeBinop::new(next(), next())),
+            Op::Min => NodeKind::Min(NodeBinop::new(next(), next())),
+            Op::Max => NodeKind::Max(NodeBinop::new(next(), next())),
+            Op::Atan2 => NodeKind::Atan2(NodeBinop::new(next(), next())),
+            Op::Floor => NodeKind::Floor(NodeUnop::new(next())),
+            Op::Fract => NodeKind::Fract(NodeUnop::new(next())),
+            Op::Mix => NodeKind::Mix(NodeTernop::new(next(), next(), next())),
+            Op::Smoothstep => NodeKind::Smoothstep(NodeTernop::new(next(), next(), next())),
+            Op::Clamp => NodeKind::Clamp(NodeTernop::new(next(), next(), next())),
+            Op::Lt => NodeKind::Lt(NodeBinop::new(next(), next())),
+            Op::If => NodeKind::If(NodeTernop::new(next(), next(), next())),
+            Op::Radius => NodeKind::Radius,
+            Op::Angle => NodeKind::Angle,
+            Op::T => NodeKind::T,
+            Op::Saw => NodeKind::Saw,
+            Op::Random | Op::DistTo | Op::SinT => return None,
+        };
+        Some(node)
+    }
+}
+
+/// One weighted alternative of a rule, e.g. `add(C, C)`
+#[derive(Debug, Clone)]
+pub struct Production {
+    pub op: Op,
+    /// Non-terminals expanded into the children of `op`
+    pub args: Vec<String>,
+    pub weight: u32,
+}
+
+impl Production {
+    pub fn new(op: Op, args: &[&str], weight: u32) -> Self {
+        Production {
+            op,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            weight,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rule {
+    pub productions: Vec<Production>,
+    /// Non-terminal expanded instead of this rule when the depth runs out
+    /// or when the `terminal_probability` roll succeeds
+    pub terminal: Option<String>,
+    pub terminal_probability: f64,
+}
+
+impl Rule {
+    /// `None` when the weights don't fit in a `u32`
+    fn total_weight(&self) -> Option<u32> {
+        self.productions
+            .iter()
+            .try_fold(0u32, |total, p| total.checked_add(p.weight))
+    }
+
+    fn pick(&self, rng: &mut StdRng) -> &Production {
+        let mut roll = rng.gen_range(0..self.total_weight().unwrap());
+        self.productions
+            .iter()
+            .find(|production| {
+                if roll < production.weight {
+                    true
+                } else {
+                    roll -= production.weight;
+                    false
+                }
+            })
+            .unwrap()
+    }
+}
+
+/// Set of named non-terminals used by `generate_tree` to build expressions
+#[derive(Resource, Debug, Clone)]
+pub struct Grammar {
+    pub entry: String,
+    rules: HashMap<String, Rule>,
+}
+
+impl Grammar {
+    pub fn new(entry: &str, rules: HashMap<String, Rule>) -> Result<Self, RuleError> {
+        let grammar = Grammar {
+            entry: entry.to_string(),
+            rules,
+        };
+        grammar.validate()?;
+        Ok(grammar)
+    }
+
+    pub fn rule(&self, name: &str) -> &Rule {
+        &self.rules[name]
+    }
+
+    /// Fingerprint of the rules, equal grammars have the same version no
+    /// matter which file they were loaded from
+    pub fn version(&self) -> String {
+        // FNV-1a, unlike `DefaultHasher` it's stable across Rust releases
+        let hash = self
+            .canonical()
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash: u64, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+        format!("{:016x}", hash)
+    }
+
+    /// One line per item of the grammar, with the rules sorted by name, the
+    /// input of `version`
+    fn canonical(&self) -> String {
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort_by_key(|(name, _)| *name);
+
+        let mut lines = vec![format!("entry {}", self.entry)];
+        for (name, rule) in rules {
+            lines.push(format!("rule {}", name));
+            for production in &rule.productions {
+                lines.push(format!(
+                    "production {} {} {} {}",
+                    production.op.name(),
+                    production.weight,
+                    production.args.len(),
+                    production.args.join(" ")
+                ));
+            }
+            lines.push(format!(
+                "terminal {} {:016x}",
+                rule.terminal.as_deref().unwrap_or("-"),
+                rule.terminal_probability.to_bits()
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn validate(&self) -> Result<(), RuleError> {
+        if !self.rules.contains_key(&self.entry) {
+            return Err(RuleError::new(&self.entry, "is not defined".to_string()));
+        }
+
+        for (name, rule) in self.rules.iter() {
+            let error = |message: String| Err(RuleError::new(name, message));
+
+            match rule.total_weight() {
+                None => return error("has weights adding up to more than 4294967295".to_string()),
+                Some(0) => return error("has no weighted productions".to_string()),
+                Some(_) => {}
+            }
+            if !(0.0..=1.0).contains(&rule.terminal_probability) {
+                return error("has a terminal probability outside of 0..=1".to_string());
+            }
+
+            for production in rule.productions.iter() {
+                if production.args.len() != production.op.arity() {
+                    return error(format!(
+                        "gives `{}` {} arguments, expected {}",
+                        production.op.name(),
+                        production.args.len(),
+                        production.op.arity()
+                    ));
+                }
+                if let Some(arg) = production
+                    .args
+                    .iter()
+                    .find(|a| !self.rules.contains_key(*a))
+                {
+                    return error(format!("uses undefined rule `{}`", arg));
+                }
+            }
+
+            let recursive = rule.productions.iter().any(|p| !p.args.is_empty());
+            match &rule.terminal {
+                None if recursive => {
+                    return error("is recursive but has no terminal rule".to_string())
+                }
+                None => {}
+                Some(terminal) => match self.rules.get(terminal) {
+                    None => return error(format!("uses undefined rule `{}`", terminal)),
+                    // Terminal rules are expanded at depth 0, so they can't recurse
+                    Some(t) if t.productions.iter().any(|p| !p.args.is_empty()) => {
+                        return error(format!("falls back to recursive rule `{}`", terminal))
+                    }
+                    Some(_) => {}
+                },
+            }
+
+            // Terminals are followed without using up depth, a loop would
+            // never reach a production
+            let mut visited = vec![name];
+            let mut next = rule.terminal.as_ref();
+            while let Some(terminal) = next {
+                if visited.contains(&terminal) {
+                    return error(format!("falls back to itself through `{}`", terminal));
+                }
+                visited.push(terminal);
+                next = self.rules.get(terminal).and_then(|t| t.terminal.as_ref());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expand `symbol` into a tree at most `depth` levels deep
+    pub fn expand(&self, symbol: &str, depth: u32, rng: &mut StdRng) -> NodeKind {
+        let rule = self.rule(symbol);
+
+        if let Some(terminal) = &rule.terminal {
+            if depth == 0 || rng.gen_bool(rule.terminal_probability) {
+                return self.expand(terminal, depth, rng);
+            }
+        }
+
+        let production = rule.pick(rng);
+        let args = production
+            .args
+            .iter()
+            .map(|arg| self.expand(arg, depth.saturating_sub(1), rng))
+            .collect();
+        production.op.build(args, rng)
+    }
+}
+
+impl Default for Grammar {
+    /// The original randomart rules, `A` for terminals and `C` for operators
+    fn default() -> Self {
+        let rules = HashMap::from([
+            (
+                "A".to_string(),
+                Rule {
+                    productions: vec![
+                        Production::new(Op::X, &[], 1),
+                        Production::new(Op::Y, &[], 1),
+                        Production::new(Op::Random, &[], 1),
+                        Production::new(Op::Time, &[], 1),
+                    ],
+                    ..default()
+                },
+            ),
+            (
+                "C".to_string(),
+                Rule {
+                    productions: vec![
+                        Production::new(Op::Add, &["C", "C"], 1),
+                        Production::new(Op::Mult, &["C", "C"], 1),
+                        Production::new(Op::Sqrt, &["C"], 1),
+                        Production::new(Op::Abs, &["C"], 1),
+                        Production::new(Op::Sin, &["C"], 1),
+                        Production::new(Op::Mod, &["C", "C"], 1),
+                        Production::new(Op::Gt, &["C", "C"], 1),
+                    ],
+                    terminal: Some("A".to_string()),
+                    terminal_probability: 1f64 / 4f64,
+                },
+            ),
+        ]);
+
+        Grammar::new("C", rules).unwrap()
+    }
+}
+
+/// Rule that makes a grammar unusable, e.g. one referencing an undefined rule
+#[derive(Debug)]
+pub struct RuleError {
+    pub rule: String,
+    pub message: String,
+}
+
+impl RuleError {
+    fn new(rule: &str, message: String) -> Self {
+        RuleError {
+            rule: rule.to_string(),
+            message,
+        }
+    }
+}
+
+impl Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rule `{}` {}", self.rule, self.message)
+    }
+}
+
+impl Error for RuleError {}
+
+/// Error in a grammar file, lines and columns start at 1
+#[derive(Debug)]
+pub struct GrammarError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl GrammarError {
+    fn new(line: usize, column: usize, message: String) -> Self {
+        GrammarError {
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for GrammarError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Define,
+    Arrow,
+    Pipe,
+    OpenParen,
+    CloseParen,
+    Comma,
+    OpenBracket,
+    CloseBracket,
+}
+
+fn tokenize(line: usize, text: &str) -> Result<Vec<(usize, Token)>, GrammarError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let token = match chars[i] {
+            '#' => break,
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '|' => Token::Pipe,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ',' => Token::Comma,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            ':' if chars[i..].starts_with(&[':', ':', '=']) => {
+                i += 2;
+                Token::Define
+            }
+            '-' if chars[i..].starts_with(&['-', '>']) => {
+                i += 1;
+                Token::Arrow
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i + 1 < chars.len() && (chars[i + 1].is_alphanumeric() || chars[i + 1] == '_')
+                {
+                    i += 1;
+                }
+                Token::Ident(chars[start..=i].iter().collect())
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                while i + 1 < chars.len() && (chars[i + 1].is_ascii_digit() || chars[i + 1] == '.')
+                {
+                    i += 1;
+                }
+                Token::Number(chars[start..=i].iter().collect())
+            }
+            c => {
+                return Err(GrammarError::new(
+                    line,
+                    start + 1,
+                    format!("unexpected character `{}`", c),
+                ))
+            }
+        };
+        tokens.push((start + 1, token));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Cursor over the tokens of a single line
+struct LineParser {
+    line: usize,
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl LineParser {
+    fn error<T>(&self, column: usize, message: String) -> Result<T, GrammarError> {
+        Err(GrammarError::new(self.line, column, message))
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |(column, _)| *column)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.tokens.len()
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.tokens.get(self.pos).is_some_and(|(_, t)| t == token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), GrammarError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            self.error(self.column(), format!("expected {}", expected))
+        }
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<(usize, String), GrammarError> {
+        match self.tokens.get(self.pos) {
+            Some((column, Token::Ident(name))) => {
+                self.pos += 1;
+                Ok((*column, name.clone()))
+            }
+            _ => self.error(self.column(), format!("expected {}", expected)),
+        }
+    }
+
+    fn number<T: FromStr>(&mut self, expected: &str) -> Result<T, GrammarError> {
+        let column = self.column();
+        match self.tokens.get(self.pos) {
+            Some((_, Token::Number(number))) => {
+                self.pos += 1;
+                number
+                    .parse()
+                    .or_else(|_| self.error(column, format!("invalid {} `{}`", expected, number)))
+            }
+            _ => self.error(column, format!("expected {}", expected)),
+        }
+    }
+}
+
+/// Rules as they are read, before the grammar is validated
+#[derive(Default)]
+struct GrammarBuilder {
+    entry: Option<String>,
+    rules: HashMap<String, Rule>,
+    /// Where each rule was first mentioned, to locate rule errors
+    declared: HashMap<String, (usize, usize)>,
+    /// Every rule reference, to report undefined ones where they are used
+    references: Vec<(usize, usize, String)>,
+}
+
+impl GrammarBuilder {
+    fn rule(&mut self, line: usize, column: usize, name: &str) -> &mut Rule {
+        self.declared
+            .entry(name.to_string())
+            .or_insert((line, column));
+        self.rules.entry(name.to_string()).or_default()
+    }
+
+    fn alternatives(&mut self, parser: &mut LineParser, name: &str) -> Result<(), GrammarError> {
+        loop {
+            let production = self.production(parser)?;
+            self.rules
+                .get_mut(name)
+                .unwrap()
+                .productions
+                .push(production);
+
+            if parser.at_end() {
+                return Ok(());
+            }
+            parser.expect(Token::Pipe, "`|`")?;
+        }
+    }
+
+    fn production(&mut self, parser: &mut LineParser) -> Result<Production, GrammarError> {
+        let (column, name) = parser.ident("an operator")?;
+        let Some(op) = Op::from_name(&name) else {
+            return parser.error(column, format!("unknown operator `{}`", name));
+        };
+
+        let mut args = Vec::new();
+        if parser.eat(&Token::OpenParen) {
+            loop {
+                let (arg_column, arg) = parser.ident("a rule name")?;
+                self.references.push((parser.line, arg_column, arg.clone()));
+                args.push(arg);
+                if !parser.eat(&Token::Comma) {
+                    break;
+                }
+            }
+            parser.expect(Token::CloseParen, "`)`")?;
+        }
+        if args.len() != op.arity() {
+            return parser.error(
+                column,
+                format!(
+                    "`{}` takes {} arguments, got {}",
+                    name,
+                    op.arity(),
+                    args.len()
+                ),
+            );
+        }
+
+        let weight = if parser.eat(&Token::OpenBracket) {
+            let weight = parser.number("weight")?;
+            parser.expect(Token::CloseBracket, "`]`")?;
+            weight
+        } else {
+            1
+        };
+
+        Ok(Production { op, args, weight })
+    }
+
+    fn build(self) -> Result<Grammar, GrammarError> {
+        let Some(entry) = self.entry else {
+            return Err(GrammarError::new(1, 1, "grammar has no rules".to_string()));
+        };
+
+        if let Some((line, column, name)) = self
+            .references
+            .iter()
+            .find(|(_, _, name)| !self.rules.contains_key(name))
+        {
+            return Err(GrammarError::new(
+                *line,
+                *column,
+                format!("undefined rule `{}`", name),
+            ));
+        }
+
+        let declared = self.declared;
+        Grammar::new(&entry, self.rules).map_err(|error| {
+            let (line, column) = declared.get(&error.rule).copied().unwrap_or((1, 1));
+            GrammarError::new(line, column, error.to_string())
+        })
+    }
+}
+
+/// Parse the grammar file format:
+///
+/// ```text
+/// # The first rule defined is where generation starts
+/// C ::= add(C, C) | sin(C) [2]
+///     | mult(C, C)
+/// A ::= x | y | random | time
+/// # Expand A instead of C at depth 0, and otherwise a quarter of the time
+/// C -> A 0.25
+/// ```
+impl FromStr for Grammar {
+    type Err = GrammarError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut builder = GrammarBuilder::default();
+        let mut current: Option<String> = None;
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let mut parser = LineParser {
+                line,
+                tokens: tokenize(line, text)?,
+                pos: 0,
+                end: text.chars().count() + 1,
+            };
+
+            if parser.at_end() {
+                continue;
+            }
+
+            let column = parser.column();
+            if parser.eat(&Token::Pipe) {
+                let Some(name) = current.clone() else {
+                    return parser.error(column, "`|` outside of a rule".to_string());
+                };
+                builder.alternatives(&mut parser, &name)?;
+                continue;
+            }
+
+            let (column, name) = parser.ident("a rule name")?;
+            if parser.eat(&Token::Define) {
+                if builder
+                    .rules
+                    .get(&name)
+                    .is_some_and(|r| !r.productions.is_empty())
+                {
+                    return parser.error(column, format!("rule `{}` is already defined", name));
+                }
+                builder.rule(line, column, &name);
+                builder.entry.get_or_insert(name.clone());
+                builder.alternatives(&mut parser, &name)?;
+                current = Some(name);
+            } else if parser.eat(&Token::Arrow) {
+                let (terminal_column, terminal) = parser.ident("a terminal rule name")?;
+                let probability = parser.number("probability")?;
+                if !parser.at_end() {
+                    return parser.error(parser.column(), "expected end of line".to_string());
+                }
+                builder
+                    .references
+                    .push((line, terminal_column, terminal.clone()));
+                let rule = builder.rule(line, column, &name);
+                rule.terminal = Some(terminal);
+                rule.terminal_probability = probability;
+                current = None;
+            } else {
+                return parser.error(parser.column(), "expected `::=` or `->`".to_string());
+            }
+        }
+
+        builder.build()
+    }
+}
+
+#[derive(Asset, TypePath, Debug)]
+pub struct GrammarAsset(pub Grammar);
+
+#[derive(Debug)]
+pub enum GrammarLoaderError {
+    Io(std::io::Error),
+    Parse(GrammarError),
+}
+
+impl Display for GrammarLoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrammarLoaderError::Io(error) => write!(f, "could not read grammar: {}", error),
+            GrammarLoaderError::Parse(error) => write!(f, "invalid grammar at {}", error),
+        }
+    }
+}
+
+impl Error for GrammarLoaderError {}
+
+impl From<std::io::Error> for GrammarLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        GrammarLoaderError::Io(error)
+    }
+}
+
+impl From<GrammarError> for GrammarLoaderError {
+    fn from(error: GrammarError) -> Self {
+        GrammarLoaderError::Parse(error)
+    }
+}
+
+#[derive(Default)]
+struct GrammarLoader;
+
+impl AssetLoader for GrammarLoader {
+    type Asset = GrammarAsset;
+    type Settings = ();
+    type Error = GrammarLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let source = String::from_utf8(bytes)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+
+        Ok(GrammarAsset(source.parse()?))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["grammar"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// `generate_tree` from before grammars, kept to check that the default
+    /// grammar draws the same trees for the same seed
+    fn classic_tree(depth: u32, rng: &mut StdRng) -> NodeKind {
+        let terminal = depth == 0 || rng.gen_bool(1f64 / 4f64);
+        if terminal {
+            return match rng.gen_range(1..=4) {
+                1 => NodeKind::X,
+                2 => NodeKind::Y,
+                3 => NodeKind::Random(rng.gen_range(-1f32..=1f32)),
+                _ => NodeKind::Time,
+            };
+        }
+
+        let op = rng.gen_range(1..=7);
+        let mut child = || Box::new(classic_tree(depth - 1, rng));
+        match op {
+            1 => NodeKind::Add(NodeBinop::new(child(), child())),
+            2 => NodeKind::Mult(NodeBinop::new(child(), child())),
+            3 => NodeKind::Sqrt(NodeUnop::new(child())),
+            4 => NodeKind::Abs(NodeUnop::new(child())),
+            5 => NodeKind::Sin(NodeUnop::new(child())),
+            6 => NodeKind::Mod(NodeBinop::new(child(), child())),
+            _ => NodeKind::Gt(NodeBinop::new(child(), child())),
+        }
+    }
+
+    fn parse_error(source: &str) -> String {
+        source.parse::<Grammar>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn default_grammar_file_is_built_in_grammar() {
+        let grammar: Grammar = include_str!("../assets/grammars/default.grammar")
+            .parse()
+            .unwrap();
+        assert_eq!(grammar.version(), Grammar::default().version());
+    }
+
+    #[test]
+    fn extended_grammar_parses() {
+        include_str!("../assets/grammars/extended.grammar")
+            .parse::<Grammar>()
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_terminal_loops() {
+        assert!(parse_error("A ::= x\nA -> A 0.5").contains("falls back to itself"));
+        assert!(
+            parse_error("C ::= sin(C)\nC -> A 0.5\nA ::= x\nA -> B 0\nB ::= y\nB -> A 0")
+                .contains("falls back to itself")
+        );
+        assert!("C ::= sin(C)\nC -> A 0.5\nA ::= x\nA -> B 0\nB ::= y"
+            .parse::<Grammar>()
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_overflowing_weights() {
+        assert!(parse_error("A ::= x [4294967295] | y [4294967295]").contains("weights"));
+    }
+
+    #[test]
+    fn default_grammar_matches_classic_generator() {
+        let grammar = Grammar::default();
+        for seed in [0, 1, 7, 42, 1234, u64::MAX] {
+            for depth in [0, 3, 12] {
+                let mut classic = StdRng::seed_from_u64(seed);
+                let mut expanded = StdRng::seed_from_u64(seed);
+                assert_eq!(
+                    grammar.expand(&grammar.entry, depth, &mut expanded),
+                    classic_tree(depth, &mut classic),
+                    "seed {} depth {}",
+                    seed,
+                    depth
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn version_hashes_canonical_grammar() {
+        let version = |source: &str| source.parse::<Grammar>().unwrap().version();
+        let base = version("C ::= sin(C) [2] | add(A, C)\nC -> A 0.25\nA ::= x | y");
+        // Spacing and comments don't matter
+        assert_eq!(
+            base,
+            version("# same\nC ::= sin(C)  [2] | add(A,C)\n\nC -> A 0.25\nA ::= x|y")
+        );
+        for changed in [
+            "C ::= sin(C) [3] | add(A, C)\nC -> A 0.25\nA ::= x | y",
+            "C ::= cos(C) [2] | add(A, C)\nC -> A 0.25\nA ::= x | y",
+            "C ::= sin(C) [2] | add(C, A)\nC -> A 0.25\nA ::= x | y",
+            "C ::= sin(C) [2] | add(A, C)\nC -> A 0.2500001\nA ::= x | y",
+            "C ::= sin(C) [2] | add(A, C)\nC -> B 0.25\nA ::= x | y\nB ::= x | y",
+            "C ::= sin(C) [2] | add(A, C)\nC -> A 0.25\nA ::= y | x",
+        ] {
+            assert_ne!(base, version(changed), "{}", changed);
+        }
+    }
+}
@@ -3,28 +3,71 @@
 // workflow treats them as errors, so this allows them throughout the project.
 // Feel free to delete this line.
 #![allow(clippy::too_many_arguments)]
+#![feature(portable_simd)]
 
+mod animation;
+mod artwork;
+mod batch;
+mod bytecode;
+mod cli;
+mod clock;
+mod closure;
+mod drop;
+mod export;
+mod formula;
+mod formula_editor;
+mod frames;
 mod func_gen;
 mod gpu_draw;
+mod grammar;
+mod optimize;
 mod render;
+mod screenshot;
 mod seed;
+mod simd;
 mod state;
+mod supersampling;
+mod video;
 mod visibility;
 
+use animation::AnimationPlugin;
+use artwork::ArtworkPlugin;
 use bevy::asset::AssetMetaCheck;
 use bevy::prelude::*;
 use bevy::window::WindowResolution;
+use cli::{parse_app_args, run_headless, HeadlessOptions};
+use clock::ClockPlugin;
+use drop::DropPlugin;
+use export::ExportPlugin;
+use formula_editor::FormulaEditorPlugin;
 use func_gen::*;
 use gpu_draw::GpuRenderPlugin;
+use grammar::GrammarPlugin;
 use render::{generate_image, CpuRenderPlugin};
+use screenshot::ScreenshotPlugin;
 use seed::{Seed, SeedPlugin};
 use state::StatePlugin;
+use supersampling::SupersamplingPlugin;
 use visibility::VisibilityPlugin;
 
 const IMAGE_WIDTH: u32 = 800;
 const IMAGE_HEIGHT: u32 = 800;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        if let Err(error) = HeadlessOptions::parse(&args).and_then(run_headless) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let options = parse_app_args(&args).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+
     App::new()
         .add_plugins(
             DefaultPlugins
@@ -54,6 +97,19 @@ fn main() {
         .add_plugins(SeedPlugin)
         .add_plugins(GpuRenderPlugin)
         .add_plugins(StatePlugin)
+        .add_plugins(GrammarPlugin {
+            path: options.grammar,
+        })
+        .add_plugins(AnimationPlugin)
+        .add_plugins(ClockPlugin)
+        .add_plugins(ArtworkPlugin {
+            loaded: options.loaded,
+        })
+        .add_plugins(FormulaEditorPlugin)
+        .add_plugins(SupersamplingPlugin)
+        .add_plugins(ExportPlugin)
+        .add_plugins(ScreenshotPlugin)
+        .add_plugins(DropPlugin)
         .run();
 }
 
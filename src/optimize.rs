@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use crate::{
+    eval,
+    func_gen::{shader_code_with, NodeBinop, NodeKind, NodeUnop},
+};
+
+/// Fold constant subtrees and drop operations that leave their operand
+/// unchanged. The result evaluates to the same values as `node`
+pub fn simplify(node: &NodeKind) -> NodeKind {
+    let node = node.map_children(simplify);
+
+    match fold(&node) {
+        Some(value) => NodeKind::Random(value),
+        None => without_identity(node),
+    }
+}
+
+// Evaluated with `eval` so the folded value is exactly what the CPU renderer
+// would compute. Infinities and NaNs are kept as expressions since WGSL has
+// no literals for them
+fn fold(node: &NodeKind) -> Option<f32> {
+    let children = node.children();
+    let constant = !children.is_empty()
+        && children
+            .iter()
+            .all(|child| matches!(child, NodeKind::Random(_)));
+    if !constant {
+        return None;
+    }
+
+    let value = eval(0., 0., node, 0.);
+    value.is_finite().then_some(value)
+}
+
+fn is(node: &NodeKind, value: f32) -> bool {
+    matches!(node, NodeKind::Random(r) if *r == value)
+}
+
+fn without_identity(node: NodeKind) -> NodeKind {
+    match node {
+        NodeKind::Add(NodeBinop { lhs, rhs }) if is(&rhs, 0.) => *lhs,
+        NodeKind::Add(NodeBinop { lhs, rhs }) if is(&lhs, 0.) => *rhs,
+        NodeKind::Sub(NodeBinop { lhs, rhs }) if is(&rhs, 0.) => *lhs,
+        NodeKind::Mult(NodeBinop { lhs, rhs }) if is(&rhs, 1.) => *lhs,
+        NodeKind::Mult(NodeBinop { lhs, rhs }) if is(&lhs, 1.) => *rhs,
+        NodeKind::Div(NodeBinop { lhs, rhs }) if is(&rhs, 1.) => *lhs,
+        NodeKind::Min(NodeBinop { lhs, rhs }) | NodeKind::Max(NodeBinop { lhs, rhs })
+            if lhs == rhs =>
+        {
+            *lhs
+        }
+        // Both already give non-negative values
+        NodeKind::Abs(NodeUnop { value })
+            if matches!(*value, NodeKind::Abs(_) | NodeKind::Sqrt(_)) =>
+        {
+            *value
+        }
+        // `sqrt` takes the absolute value itself
+        NodeKind::Sqrt(NodeUnop { value }) => match *value {
+            NodeKind::Abs(inner) => NodeKind::Sqrt(inner),
+            value => NodeKind::Sqrt(NodeUnop::new(Box::new(value))),
+        },
+        NodeKind::Floor(NodeUnop { value }) if matches!(*value, NodeKind::Floor(_)) => *value,
+        NodeKind::If(node_ternop) => match *node_ternop.a {
+            NodeKind::Random(condition) if condition > 0. => *node_ternop.b,
+            NodeKind::Random(_) => *node_ternop.c,
+            _ => NodeKind::If(node_ternop),
+        },
+        node => node,
+    }
+}
+
+/// WGSL for several trees where every subexpression used more than once is
+/// computed a single time, returns the `let` statements and an expression
+/// per tree
+pub fn shared_shader_code<const N: usize>(roots: [&NodeKind; N]) -> (String, [String; N]) {
+    let mut counts = HashMap::new();
+    for root in roots {
+        count_subexpressions(root, &mut counts);
+    }
+
+    let mut shared = SharedCode {
+        counts,
+        names: HashMap::new(),
+        lets: String::new(),
+    };
+    let code = roots.map(|root| shared.code(root));
+    (shared.lets, code)
+}
+
+/// Trees with their equal subtrees merged into a single node, so values
+/// shared between them can be computed once. See `merge_subtrees`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dag {
+    /// Every distinct subtree, placed after its children
+    pub nodes: Vec<DagNode>,
+    /// Index in `nodes` of each tree
+    pub roots: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DagNode {
+    /// The node with placeholder children, for its kind and constants
+    pub node: NodeKind,
+    /// Index in `Dag::nodes` of each child
+    pub children: Vec<usize>,
+}
+
+/// Merge the equal subtrees of `roots`, they're compared by their WGSL
+pub fn merge_subtrees(roots: &[&NodeKind]) -> Dag {
+    let mut dag = Dag {
+        nodes: Vec::new(),
+        roots: Vec::new(),
+    };
+    let mut indices = HashMap::new();
+    for root in roots {
+        let index = merge(root, &mut dag.nodes, &mut indices);
+        dag.roots.push(index);
+    }
+    dag
+}
+
+// Keyed by the code of the node with its children replaced by their indices
+fn merge(node: &NodeKind, nodes: &mut Vec<DagNode>, indices: &mut HashMap<String, usize>) -> usize {
+    let children: Vec<usize> = node
+        .children()
+        .into_iter()
+        .map(|child| merge(child, nodes, indices))
+        .collect();
+    let mut child_indices = children.iter();
+    let key = shader_code_with(node, &mut |_| format!("#{}", child_indices.next().unwrap()));
+
+    *indices.entry(key).or_insert_with(|| {
+        nodes.push(DagNode {
+            node: node.map_children(|_| NodeKind::X),
+            children,
+        });
+        nodes.len() - 1
+    })
+}
+
+// Keyed by the plain WGSL of the subtree
+fn count_subexpressions(node: &NodeKind, counts: &mut HashMap<String, usize>) -> String {
+    let code = shader_code_with(node, &mut |child| count_subexpressions(child, counts));
+    *counts.entry(code.clone()).or_default() += 1;
+    code
+}
+
+struct SharedCode {
+    counts: HashMap<String, usize>,
+    /// `let` bound name of each repeated subexpression
+    names: HashMap<String, String>,
+    lets: String,
+}
+
+impl SharedCode {
+    /// Code of `node` referring to the shared subexpressions by name
+    fn code(&mut self, node: &NodeKind) -> String {
+        self.code_and_key(node).0
+    }
+
+    /// Code of `node` along with the plain WGSL used as its key
+    fn code_and_key(&mut self, node: &NodeKind) -> (String, String) {
+        let mut keys = Vec::new();
+        let code = shader_code_with(node, &mut |child| {
+            let (code, key) = self.code_and_key(child);
+            keys.push(key);
+            code
+        });
+        let mut children = keys.into_iter();
+        let key = shader_code_with(node, &mut |_| children.next().unwrap());
+
+        if let Some(name) = self.names.get(&key) {
+            return (name.clone(), key);
+        }
+        // Constants and `t` are as cheap as a variable
+        let worth_sharing = !matches!(node, NodeKind::Random(_) | NodeKind::T);
+        if worth_sharing && self.counts[&key] > 1 {
+            let name = format!("shared_{}", self.names.len());
+            self.lets += &format!("let {} = {};\n", name, code);
+            self.names.insert(key.clone(), name.clone());
+            return (name, key);
+        }
+        (code, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        func_gen::tests::{assert_matches_eval, sample_trees},
+        grammar::Op,
+    };
+
+    fn call(op: Op, args: Vec<NodeKind>) -> NodeKind {
+        op.apply(args).unwrap()
+    }
+
+    #[test]
+    fn simplify_keeps_values() {
+        for tree in sample_trees() {
+            let simplified = simplify(&tree);
+            assert_matches_eval(&tree, |x, y, time| eval(x, y, &simplified, time));
+        }
+    }
+
+    #[test]
+    fn simplify_drops_identities() {
+        let (x, y) = (NodeKind::X, NodeKind::Y);
+        let constant = NodeKind::Random;
+        for (node, expected) in [
+            (
+                call(Op::Sqrt, vec![call(Op::Abs, vec![x.clone()])]),
+                call(Op::Sqrt, vec![x.clone()]),
+            ),
+            (
+                call(Op::Abs, vec![call(Op::Abs, vec![y.clone()])]),
+                call(Op::Abs, vec![y.clone()]),
+            ),
+            (
+                call(Op::Abs, vec![call(Op::Sqrt, vec![y.clone()])]),
+                call(Op::Sqrt, vec![y.clone()]),
+            ),
+            (call(Op::Add, vec![x.clone(), constant(0.)]), x.clone()),
+            (call(Op::Mult, vec![constant(1.), y.clone()]), y.clone()),
+            (call(Op::Div, vec![x.clone(), constant(1.)]), x.clone()),
+            (call(Op::Min, vec![y.clone(), y.clone()]), y.clone()),
+            (
+                call(Op::Floor, vec![call(Op::Floor, vec![x.clone()])]),
+                call(Op::Floor, vec![x.clone()]),
+            ),
+            (
+                call(Op::If, vec![constant(0.5), x.clone(), y.clone()]),
+                x.clone(),
+            ),
+            (
+                call(Op::Add, vec![constant(0.25), constant(0.5)]),
+                constant(0.75),
+            ),
+            // Folded to `x * 1` first, then the identity goes
+            (
+                call(
+                    Op::Mult,
+                    vec![x.clone(), call(Op::Add, vec![constant(0.5), constant(0.5)])],
+                ),
+                x.clone(),
+            ),
+            // Infinities stay expressions
+            (
+                call(Op::Exp, vec![constant(1000.)]),
+                call(Op::Exp, vec![constant(1000.)]),
+            ),
+        ] {
+            assert_eq!(simplify(&node), expected);
+        }
+    }
+
+    #[test]
+    fn shares_repeated_subexpressions() {
+        let repeated = call(
+            Op::Sin,
+            vec![call(Op::Mult, vec![NodeKind::X, NodeKind::Y])],
+        );
+        let r = call(Op::Add, vec![repeated.clone(), NodeKind::T]);
+        let g = call(Op::Abs, vec![repeated.clone()]);
+        let b = NodeKind::T;
+
+        let (lets, [r, g, b]) = shared_shader_code([&r, &g, &b]);
+        // `x` and `y` are repeated too and get their own lets first
+        let sines: Vec<&str> = lets.lines().filter(|line| line.contains("sin(")).collect();
+        assert_eq!(sines.len(), 1, "{}", lets);
+        let name = sines[0]
+            .strip_prefix("let ")
+            .and_then(|line| line.split(" = ").next())
+            .unwrap();
+        assert!(name.starts_with("shared_"), "{}", lets);
+        assert!(r.contains(name) && g.contains(name), "{} {}", r, g);
+        assert!(!b.contains(name), "{}", b);
+    }
+
+    #[test]
+    fn merges_equal_subtrees() {
+        let repeated = || {
+            call(
+                Op::Sin,
+                vec![call(Op::Mult, vec![NodeKind::X, NodeKind::Y])],
+            )
+        };
+        // Separate copies, equal only by structure
+        let r = call(Op::Add, vec![repeated(), NodeKind::T]);
+        let g = call(Op::Abs, vec![repeated()]);
+        let b = repeated();
+
+        let dag = merge_subtrees(&[&r, &g, &b]);
+        // x, y, x * y, sin, t, add and abs
+        assert_eq!(dag.nodes.len(), 7);
+        let sin = dag.roots[2];
+        assert_eq!(dag.nodes[sin].node.op(), Op::Sin);
+        assert_eq!(dag.nodes[dag.roots[0]].children[0], sin);
+        assert_eq!(dag.nodes[dag.roots[1]].children, [sin]);
+    }
+}
@@ -1,57 +1,374 @@
+use std::{collections::VecDeque, ops::Range, sync::Arc};
+
+use crate::{
+    animation::AnimationTime,
+    artwork::Artwork,
+    bytecode::Program,
+    clock::RenderClock,
+    closure::{compile, Function},
+    optimize::{merge_subtrees, Dag},
+    simd::eval_batch,
+    state::RenderState,
+    supersampling::Supersampling,
+};
 use bevy::{
     asset::RenderAssetUsages,
     color::palettes::css,
+    input::common_conditions::input_just_pressed,
     prelude::*,
     render::render_resource::{Extent3d, TextureDimension, TextureFormat},
-    tasks::{ComputeTaskPool, ParallelSlice},
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, ComputeTaskPool, ParallelSlice, Task},
     window::WindowResized,
 };
-use rand::SeedableRng;
-
-use crate::{eval, generate_tree, seed::Seed, state::RenderState};
 
 pub struct CpuRenderPlugin;
 
 impl Plugin for CpuRenderPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (render).run_if(should_run));
+        app.init_resource::<CpuAnimation>()
+            .init_resource::<CpuBackend>()
+            .init_resource::<CpuRenderTasks>()
+            .add_systems(Startup, setup_image)
+            .add_systems(
+                Update,
+                toggle_cpu_animation.run_if(input_just_pressed(KeyCode::KeyP)),
+            )
+            .add_systems(
+                Update,
+                cycle_target_fps.run_if(input_just_pressed(KeyCode::KeyO)),
+            )
+            .add_systems(
+                Update,
+                switch_cpu_backend.run_if(input_just_pressed(KeyCode::KeyB)),
+            )
+            .add_systems(
+                Update,
+                (
+                    render.run_if(should_run),
+                    render_next_frame.run_if(animation_frame_due),
+                    receive_tiles,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Keeps re-rendering the CPU image so it animates like the GPU shader.
+/// `P` pauses it and `O` cycles through target frame rates
+#[derive(Resource, Debug, Clone)]
+pub struct CpuAnimation {
+    pub playing: bool,
+    /// Re-render rate, `None` renders every frame
+    pub target_fps: Option<f32>,
+}
+
+impl Default for CpuAnimation {
+    fn default() -> Self {
+        CpuAnimation {
+            playing: true,
+            target_fps: None,
+        }
+    }
+}
+
+/// How the CPU renderer evaluates the trees, all give the same image
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CpuBackend {
+    /// Stack machine from `bytecode`
+    Bytecode,
+    /// Nested closures from `closure`
+    Closures,
+    /// Whole rows at once with `simd::eval_batch`
+    #[default]
+    Simd,
+}
+
+/// The r, g and b trees compiled for one of the `CpuBackend`s
+enum Compiled {
+    Bytecode([Program; 3]),
+    Closure([Function; 3]),
+    /// Subtrees the channels have in common are evaluated once per row
+    Simd(Dag),
+}
+
+impl Compiled {
+    fn new(artwork: &Artwork, backend: CpuBackend) -> Self {
+        let trees = [&artwork.r, &artwork.g, &artwork.b];
+        match backend {
+            CpuBackend::Bytecode => Compiled::Bytecode(trees.map(Program::compile)),
+            CpuBackend::Closures => Compiled::Closure(trees.map(compile)),
+            CpuBackend::Simd => Compiled::Simd(merge_subtrees(&trees)),
+        }
+    }
+
+    /// Scratch space for `eval_rows`, a stack per program or a row per
+    /// `Dag` node, reuse it across rows
+    fn stacks(&self) -> Vec<Vec<f32>> {
+        match self {
+            Compiled::Bytecode(programs) => programs.iter().map(Program::stack).collect(),
+            Compiled::Closure(_) | Compiled::Simd(_) => Vec::new(),
+        }
+    }
+
+    /// Evaluate the r, g and b values of the pixels at `(xs[i], ys[i])`
+    fn eval_rows(
+        &self,
+        xs: &[f32],
+        ys: &[f32],
+        time: f32,
+        stacks: &mut Vec<Vec<f32>>,
+        rows: &mut [Vec<f32>; 3],
+    ) {
+        match self {
+            Compiled::Bytecode(programs) => {
+                for ((program, stack), row) in programs.iter().zip(stacks).zip(rows) {
+                    for (value, (x, y)) in row.iter_mut().zip(xs.iter().zip(ys)) {
+                        *value = program.run(*x, *y, time, stack);
+                    }
+                }
+            }
+            Compiled::Closure(functions) => {
+                for (function, row) in functions.iter().zip(rows) {
+                    for (value, (x, y)) in row.iter_mut().zip(xs.iter().zip(ys)) {
+                        *value = function(*x, *y, time);
+                    }
+                }
+            }
+            Compiled::Simd(dag) => eval_batch(
+                xs,
+                ys,
+                dag,
+                time,
+                stacks,
+                rows.each_mut().map(|row| row.as_mut_slice()),
+            ),
+        }
     }
 }
 
+/// Rows rendered by one task
+const TILE_ROWS: usize = 16;
+/// Size of the blocks of the coarse pass shown while the tiles render
+const PREVIEW_SCALE: usize = 8;
+
+/// Tiles of the CPU render in flight, copied into `CpuImage` in order as they
+/// finish. Replacing them drops and so cancels the stale tasks
+#[derive(Resource, Default)]
+struct CpuRenderTasks {
+    tiles: VecDeque<Task<Tile>>,
+    /// Show each tile as soon as it's done instead of the whole frame at once
+    progressive: bool,
+}
+
+/// RGBA bytes of the image rows starting at `start`
+struct Tile {
+    start: usize,
+    data: Vec<u8>,
+}
+
+/// The one image the CPU renderer draws into, resized along with the window
+#[derive(Resource)]
+pub struct CpuImage(pub Handle<Image>);
+
+fn setup_image(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    commands.insert_resource(CpuImage(images.add(generate_image(1, 1))));
+}
+
+fn toggle_cpu_animation(mut cpu_animation: ResMut<CpuAnimation>) {
+    cpu_animation.playing = !cpu_animation.playing;
+    info!("cpu animation playing: {}", cpu_animation.playing);
+}
+
+// Every frame, then 30, 15 and 5 frames per second
+fn cycle_target_fps(mut cpu_animation: ResMut<CpuAnimation>) {
+    cpu_animation.target_fps = match cpu_animation.target_fps {
+        None => Some(30.),
+        Some(fps) if fps > 15. => Some(15.),
+        Some(fps) if fps > 5. => Some(5.),
+        Some(_) => None,
+    };
+    match cpu_animation.target_fps {
+        Some(fps) => info!("cpu animation target: {} fps", fps),
+        None => info!("cpu animation target: every frame"),
+    }
+}
+
+fn switch_cpu_backend(mut backend: ResMut<CpuBackend>) {
+    *backend = match *backend {
+        CpuBackend::Bytecode => CpuBackend::Closures,
+        CpuBackend::Closures => CpuBackend::Simd,
+        CpuBackend::Simd => CpuBackend::Bytecode,
+    };
+    info!("cpu backend: {:?}", *backend);
+}
+
+fn animation_frame_due(
+    cpu_animation: Res<CpuAnimation>,
+    state: Res<State<RenderState>>,
+    clock: Res<RenderClock>,
+    time: Res<Time>,
+    mut last_frame: Local<f32>,
+) -> bool {
+    // A paused clock would redraw the same image
+    if !cpu_animation.playing || !clock.is_changed() || *state.get() != RenderState::CpuRender {
+        return false;
+    }
+
+    let now = time.elapsed_secs();
+    let due = cpu_animation
+        .target_fps
+        .is_none_or(|fps| now - *last_frame >= 1. / fps);
+    if due {
+        *last_frame = now;
+    }
+    due
+}
+
 fn should_run(
     mut resize_reader: EventReader<WindowResized>,
-    seed: Res<Seed>,
+    artwork: Res<Artwork>,
+    animation: Res<AnimationTime>,
+    backend: Res<CpuBackend>,
+    supersampling: Res<Supersampling>,
     state: Res<State<RenderState>>,
 ) -> bool {
-    (resize_reader.read().last().is_some() | seed.is_changed() | state.is_changed())
+    (resize_reader.read().last().is_some()
+        | artwork.is_changed()
+        | backend.is_changed()
+        | supersampling.is_changed()
+        | animation.is_changed()
+        | state.is_changed())
         & (*state.get() == RenderState::CpuRender)
 }
 
+// Starts over whenever what's drawn changes, with a coarse preview first
 fn render(
     mut commands: Commands,
-    mut query: Query<&mut Sprite>,
+    query: Query<&Sprite>,
+    cpu_image: Res<CpuImage>,
     mut images: ResMut<Assets<Image>>,
-    seed: Res<Seed>,
+    artwork: Res<Artwork>,
     windows: Query<&Window>,
-    time: Res<Time>,
+    clock: Res<RenderClock>,
+    animation: Res<AnimationTime>,
+    backend: Res<CpuBackend>,
+    supersampling: Res<Supersampling>,
+    mut tasks: ResMut<CpuRenderTasks>,
 ) {
     let window = windows.single();
 
+    let image = images.get_mut(&cpu_image.0).unwrap();
+
     // When resolution is being changed
-    let mut image = generate_image(
+    let size = UVec2::new(
         window.resolution.width() as u32,
         window.resolution.height() as u32,
     );
+    if image.size() != size {
+        image.resize(Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        });
+    }
+
+    *tasks = spawn_tiles(
+        size,
+        &artwork,
+        animation.at(clock.seconds),
+        *backend,
+        *supersampling,
+        true,
+    );
+
+    // The sprite is despawned when switching renderers
+    if query.is_empty() {
+        commands.spawn(Sprite::from_image(cpu_image.0.clone()));
+    }
+}
+
+// Animation frames wait for the previous one and are shown whole
+fn render_next_frame(
+    cpu_image: Res<CpuImage>,
+    images: Res<Assets<Image>>,
+    artwork: Res<Artwork>,
+    clock: Res<RenderClock>,
+    animation: Res<AnimationTime>,
+    backend: Res<CpuBackend>,
+    supersampling: Res<Supersampling>,
+    mut tasks: ResMut<CpuRenderTasks>,
+) {
+    if !tasks.tiles.is_empty() {
+        return;
+    }
+
+    let size = images.get(&cpu_image.0).unwrap().size();
+    *tasks = spawn_tiles(
+        size,
+        &artwork,
+        animation.at(clock.seconds),
+        *backend,
+        *supersampling,
+        false,
+    );
+}
 
-    render_pixels(&mut image, seed.0, time.elapsed_secs());
+fn spawn_tiles(
+    size: UVec2,
+    artwork: &Artwork,
+    time: f32,
+    backend: CpuBackend,
+    supersampling: Supersampling,
+    progressive: bool,
+) -> CpuRenderTasks {
+    let compiled = Arc::new(Compiled::new(&artwork.simplified(), backend));
+    let (width, height) = (size.x as usize, size.y as usize);
 
-    // TODO DELETE IMAGES
-    let handle = images.add(image);
-    if let Ok(mut sprite) = query.get_single_mut() {
-        sprite.image = handle.clone()
-    } else {
-        commands.spawn(Sprite::from_image(handle.clone()));
+    let mut tiles = VecDeque::new();
+    let mut spawn = |rows: Range<usize>, scale: usize, supersampling: Supersampling| {
+        let compiled = compiled.clone();
+        tiles.push_back(AsyncComputeTaskPool::get().spawn(async move {
+            Tile {
+                start: rows.start,
+                data: render_rows(&compiled, width, height, rows, scale, supersampling, time),
+            }
+        }));
     };
+
+    if progressive {
+        spawn(0..height, PREVIEW_SCALE, Supersampling::default());
+    }
+    for start in (0..height).step_by(TILE_ROWS) {
+        spawn(start..(start + TILE_ROWS).min(height), 1, supersampling);
+    }
+
+    CpuRenderTasks { tiles, progressive }
+}
+
+fn receive_tiles(
+    mut tasks: ResMut<CpuRenderTasks>,
+    cpu_image: Res<CpuImage>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let ready = match tasks.progressive {
+        true => tasks.tiles.front().is_some_and(Task::is_finished),
+        false => !tasks.tiles.is_empty() && tasks.tiles.iter().all(Task::is_finished),
+    };
+    if !ready {
+        return;
+    }
+
+    let image = images.get_mut(&cpu_image.0).unwrap();
+    let row_bytes = image.width() as usize * 4;
+    while let Some(tile) = tasks
+        .tiles
+        .front_mut()
+        .and_then(|task| block_on(poll_once(task)))
+    {
+        tasks.tiles.pop_front();
+        let start = tile.start * row_bytes;
+        image.data[start..start + tile.data.len()].copy_from_slice(&tile.data);
+    }
 }
 
 pub fn generate_image(width: u32, height: u32) -> Image {
@@ -73,26 +390,23 @@ pub fn generate_image(width: u32, height: u32) -> Image {
     )
 }
 
-fn render_pixels(image: &mut Image, seed: u64, time: f32) {
-    const MAX_DEPTH: u32 = 30;
-    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
-
-    info!("{}", seed);
-
-    let r_tree = generate_tree(MAX_DEPTH, &mut rng);
-    // info!("{:?}", r_tree);
-    let g_tree = generate_tree(MAX_DEPTH, &mut rng);
-    // info!("{:?}", g_tree);
-    let b_tree = generate_tree(MAX_DEPTH, &mut rng);
-    // info!("{:?}", b_tree);
+pub fn render_pixels(
+    image: &mut Image,
+    artwork: &Artwork,
+    time: f32,
+    backend: CpuBackend,
+    supersampling: Supersampling,
+) {
+    // Cheap next to evaluating every pixel, so it's simply redone each frame
+    let artwork = artwork.simplified();
+    let compiled = Compiled::new(&artwork, backend);
+    let width = image.width() as usize;
+    let height = image.height() as usize;
 
     //let mut buffer_r: Vec<f32> = Vec::with_capacity((image.height() * image.height()) as usize);
     //let mut buffer_g: Vec<f32> = Vec::with_capacity((image.height() * image.height()) as usize);
     //let mut buffer_b: Vec<f32> = Vec::with_capacity((image.height() * image.height()) as usize);
 
-    let width = image.width() as usize;
-    let height = image.height() as usize;
-
     //let zipped: Vec<(f32, f32, f32)> = (0..height)
     //    .into_par_iter()
     //    .flat_map(|y| {
@@ -114,27 +428,18 @@ fn render_pixels(image: &mut Image, seed: u64, time: f32) {
     let result: Vec<u8> = (0..height)
         .collect::<Vec<usize>>()
         .par_splat_map(ComputeTaskPool::get(), None, |_, data| {
-            let mut vec: Vec<f32> = vec![0.; data.len() * width * 4];
-            let mut counter: usize = 0;
-            data.iter().for_each(|y| {
-                let ny = (*y as f32) / (height as f32) * 2. - 1.;
-                (0..width).for_each(|x| {
-                    let nx = (x as f32) / (width as f32) * 2. - 1.;
-
-                    vec[0 + counter] = eval(nx, ny, &r_tree, time);
-                    vec[1 + counter] = eval(nx, ny, &g_tree, time);
-                    vec[2 + counter] = eval(nx, ny, &b_tree, time);
-                    vec[3 + counter] = 1.;
-                    counter += 4;
-                })
-            });
-            vec
+            render_rows(
+                &compiled,
+                width,
+                height,
+                data[0]..data[data.len() - 1] + 1,
+                1,
+                supersampling,
+                time,
+            )
         })
         .into_iter()
         .flatten()
-        .collect::<Vec<f32>>()
-        .iter()
-        .map(|n| ((n + 1.) / 2. * 255.) as u8)
         .collect();
 
     //let (buffer_r, buffer_g, buffer_b): (Vec<_>, Vec<_>, Vec<_>) = itertools::multiunzip(zipped);
@@ -179,15 +484,59 @@ fn render_pixels(image: &mut Image, seed: u64, time: f32) {
     //     .collect();
 
     //info!("{:#?}", interleaved);
-    *image = Image::new(
-        Extent3d {
-            width: image.width(),
-            height: image.height(),
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        result,
-        TextureFormat::Rgba8UnormSrgb,
-        RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
-    )
+    image.data = result;
+}
+
+/// RGBA bytes of the `rows` of a `width`×`height` image, evaluated once per
+/// `scale`×`scale` block of pixels. Each value averages the supersampled
+/// points of the block's corner pixel
+fn render_rows(
+    compiled: &Compiled,
+    width: usize,
+    height: usize,
+    rows: Range<usize>,
+    scale: usize,
+    supersampling: Supersampling,
+    time: f32,
+) -> Vec<u8> {
+    let offsets: Vec<f32> = supersampling.offsets().collect();
+    let columns = (0..width).step_by(scale).len();
+    let xs: Vec<f32> = (0..width)
+        .step_by(scale)
+        .flat_map(|x| {
+            offsets
+                .iter()
+                .map(move |offset| (x as f32 + offset) / (width as f32) * 2. - 1.)
+        })
+        .collect();
+    let mut ys = vec![0.; xs.len()];
+    let mut stacks = compiled.stacks();
+    let mut values = [(); 3].map(|_| vec![0.; xs.len()]);
+
+    let mut data = Vec::with_capacity(rows.len() * width * 4);
+    for y in rows.clone().step_by(scale) {
+        // Summed as bytes so a single sample gives the same image as before
+        let mut sums = vec![[0u32; 3]; columns];
+        for offset in &offsets {
+            ys.fill((y as f32 + offset) / (height as f32) * 2. - 1.);
+            compiled.eval_rows(&xs, &ys, time, &mut stacks, &mut values);
+            for (channel, values) in values.iter().enumerate() {
+                for (i, n) in values.iter().enumerate() {
+                    sums[i / offsets.len()][channel] += ((n + 1.) / 2. * 255.) as u8 as u32;
+                }
+            }
+        }
+
+        let count = (offsets.len() * offsets.len()) as f32;
+        let row: Vec<u8> = (0..width)
+            .flat_map(|x| {
+                let [r, g, b] = sums[x / scale].map(|sum| (sum as f32 / count).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect();
+        for _ in y..(y + scale).min(rows.end) {
+            data.extend_from_slice(&row);
+        }
+    }
+    data
 }
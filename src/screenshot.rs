@@ -0,0 +1,162 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use bevy::{
+    input::common_conditions::input_just_pressed,
+    prelude::*,
+    render::view::screenshot::{Screenshot, ScreenshotCaptured},
+};
+
+use crate::{
+    animation::AnimationTime, artwork::Artwork, cli::parse_value, clock::RenderClock,
+    formula::to_formula, func_gen::MAX_DEPTH, grammar::Grammar,
+};
+
+/// Saves what's on screen with `F12`, from either renderer, as a PNG
+/// carrying the `ImageMetadata` needed to render it again
+pub struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            take_screenshot.run_if(input_just_pressed(KeyCode::F12)),
+        );
+    }
+}
+
+/// Where an image came from, stored in its PNG text chunks
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageMetadata {
+    pub seed: Option<u64>,
+    pub depth: u32,
+    /// `Grammar::version` of the grammar the seed was expanded with
+    pub grammar: String,
+    /// `t` value the expressions were evaluated at
+    pub time: f32,
+    /// The r, g and b formulas, they reproduce the image even without a seed
+    pub formulas: [String; 3],
+}
+
+impl ImageMetadata {
+    pub fn new(artwork: &Artwork, depth: u32, grammar: &Grammar, time: f32) -> Self {
+        ImageMetadata {
+            seed: artwork.seed,
+            depth,
+            grammar: grammar.version(),
+            time,
+            formulas: [&artwork.r, &artwork.g, &artwork.b].map(to_formula),
+        }
+    }
+
+    /// Keyword and text of each tEXt chunk
+    fn chunks(&self) -> Vec<(&'static str, String)> {
+        let mut chunks = Vec::new();
+        if let Some(seed) = self.seed {
+            chunks.push(("Seed", seed.to_string()));
+        }
+        chunks.push(("Depth", self.depth.to_string()));
+        chunks.push(("Grammar", self.grammar.clone()));
+        chunks.push(("Time", self.time.to_string()));
+        for (name, formula) in ["R", "G", "B"].into_iter().zip(&self.formulas) {
+            chunks.push((name, formula.clone()));
+        }
+        chunks
+    }
+
+    /// Artwork drawing the stored formulas
+    pub fn artwork(&self) -> Result<Artwork, String> {
+        Ok(Artwork {
+            seed: self.seed,
+            ..Artwork::from_formulas(self.formulas.each_ref().map(String::as_str))?
+        })
+    }
+
+    /// Read the metadata written by `save_png`
+    pub fn load_png(path: &Path) -> Result<Self, String> {
+        let file = File::open(path)
+            .map_err(|error| format!("could not read {}: {}", path.display(), error))?;
+        let reader = png::Decoder::new(BufReader::new(file))
+            .read_info()
+            .map_err(|error| format!("invalid png {}: {}", path.display(), error))?;
+
+        let text = |keyword: &str| {
+            reader
+                .info()
+                .uncompressed_latin1_text
+                .iter()
+                .find(|chunk| chunk.keyword == keyword)
+                .map(|chunk| chunk.text.clone())
+                .ok_or_else(|| format!("{} has no `{}` metadata", path.display(), keyword))
+        };
+
+        Ok(ImageMetadata {
+            seed: text("Seed")
+                .ok()
+                .map(|seed| parse_value("Seed", &seed))
+                .transpose()?,
+            depth: parse_value("Depth", &text("Depth")?)?,
+            grammar: text("Grammar")?,
+            time: parse_value("Time", &text("Time")?)?,
+            formulas: [text("R")?, text("G")?, text("B")?],
+        })
+    }
+
+    /// PNG encoder for RGB images carrying the metadata in tEXt chunks
+    pub fn png_encoder<W: Write>(
+        &self,
+        writer: W,
+        width: u32,
+        height: u32,
+    ) -> Result<png::Encoder<'static, W>, String> {
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (keyword, text) in self.chunks() {
+            encoder
+                .add_text_chunk(keyword.to_string(), text)
+                .map_err(|error| error.to_string())?;
+        }
+        Ok(encoder)
+    }
+
+    /// Write `image` as an RGB PNG with the metadata in tEXt chunks
+    pub fn save_png(&self, image: &Image, path: &Path) -> Result<(), String> {
+        let rgb = image
+            .clone()
+            .try_into_dynamic()
+            .map_err(|error| error.to_string())?
+            .to_rgb8();
+        let file = File::create(path)
+            .map_err(|error| format!("could not write {}: {}", path.display(), error))?;
+
+        self.png_encoder(BufWriter::new(file), rgb.width(), rgb.height())?
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&rgb))
+            .map_err(|error| format!("could not write {}: {}", path.display(), error))
+    }
+}
+
+fn take_screenshot(
+    mut commands: Commands,
+    artwork: Res<Artwork>,
+    grammar: Res<Grammar>,
+    clock: Res<RenderClock>,
+    time: Res<Time>,
+    animation: Res<AnimationTime>,
+) {
+    let metadata = ImageMetadata::new(&artwork, MAX_DEPTH, &grammar, animation.at(clock.seconds));
+    let path = format!("screenshot-{}.png", time.elapsed().as_millis());
+
+    commands.spawn(Screenshot::primary_window()).observe(
+        move |trigger: Trigger<ScreenshotCaptured>| match metadata
+            .save_png(&trigger.event().0, Path::new(&path))
+        {
+            Ok(()) => info!("screenshot written to {}", path),
+            Err(error) => error!("{}", error),
+        },
+    );
+}
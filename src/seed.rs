@@ -11,6 +11,10 @@ use bevy::{input::common_conditions::input_just_pressed, prelude::*};
 #[derive(Resource)]
 pub struct Seed(pub u64);
 
+/// Root of the seed text box, other panels use text inputs too
+#[derive(Component)]
+struct SeedTextBox;
+
 pub struct SeedPlugin;
 
 impl Plugin for SeedPlugin {
@@ -32,13 +36,16 @@ fn reset_seed(mut seed: ResMut<Seed>) {
 
 fn spawn_text_box(mut commands: Commands) {
     commands
-        .spawn(Node {
-            width: Val::Percent(100.0),
-            height: Val::Percent(100.0),
-            align_items: AlignItems::Center,
-            justify_content: JustifyContent::Center,
-            ..default()
-        })
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            SeedTextBox,
+        ))
         .with_children(|parent| {
             parent.spawn((
                 Node {
@@ -62,10 +69,18 @@ fn spawn_text_box(mut commands: Commands) {
 fn listener(
     mut commands: Commands,
     mut events: EventReader<TextInputSubmitEvent>,
-    query: Query<Entity, With<Node>>,
+    query: Query<Entity, With<SeedTextBox>>,
+    parents: Query<&Parent>,
     mut seed: ResMut<Seed>,
 ) {
     for event in events.read() {
+        let in_text_box = parents
+            .iter_ancestors(event.entity)
+            .any(|entity| query.contains(entity));
+        if !in_text_box {
+            continue;
+        }
+
         if let Ok(number) = event.value.parse::<u64>() {
             seed.0 = number;
             query.iter().for_each(|entity| {
@@ -0,0 +1,184 @@
+use std::simd::{num::SimdFloat, Simd, StdFloat};
+
+use crate::{
+    func_gen::{safe_div, safe_log, safe_pow, safe_smoothstep, saw, NodeKind},
+    optimize::{Dag, DagNode},
+};
+
+const LANES: usize = 8;
+type Lanes = Simd<f32, LANES>;
+
+/// Evaluate each of the `dag.roots` at every `(xs[i], ys[i])` into its
+/// `outs[i]`, giving the same values as `eval`. Arithmetic runs over several
+/// pixels at once, the transcendental functions go through the scalar ones
+/// to stay exact. Every node of `dag` is evaluated once into its own row of
+/// `rows`, reuse them across calls so they're only allocated once
+pub fn eval_batch<const N: usize>(
+    xs: &[f32],
+    ys: &[f32],
+    dag: &Dag,
+    time: f32,
+    rows: &mut Vec<Vec<f32>>,
+    outs: [&mut [f32]; N],
+) {
+    rows.resize_with(dag.nodes.len(), Vec::new);
+    // Children come before their parents, so theirs are already done
+    for (index, DagNode { node, children }) in dag.nodes.iter().enumerate() {
+        let (done, rest) = rows.split_at_mut(index);
+        let out = &mut rest[0];
+        out.resize(xs.len(), 0.);
+        match node {
+            NodeKind::X => out.copy_from_slice(xs),
+            NodeKind::Y => out.copy_from_slice(ys),
+            NodeKind::Random(r) => out.fill(*r),
+            NodeKind::Add(_) => binop(done, children, out, |a, b| a + b),
+            NodeKind::Mult(_) => binop(done, children, out, |a, b| a * b),
+            NodeKind::Sqrt(_) => unop(done, children, out, |v| v.abs().sqrt()),
+            NodeKind::Abs(_) => unop(done, children, out, |v| v.abs()),
+            NodeKind::Sin(_) => unop(done, children, out, per_lane(f32::sin)),
+            NodeKind::Mod(_) => binop(done, children, out, |a, b| a % b),
+            NodeKind::Gt(_) => binop(done, children, out, per_lane2(|a, b| (a > b) as i32 as f32)),
+            NodeKind::Time => out.fill(time.sin()),
+            NodeKind::Sub(_) => binop(done, children, out, |a, b| a - b),
+            NodeKind::Div(_) => binop(done, children, out, per_lane2(safe_div)),
+            NodeKind::Cos(_) => unop(done, children, out, per_lane(f32::cos)),
+            NodeKind::Tan(_) => unop(done, children, out, per_lane(f32::tan)),
+            NodeKind::Exp(_) => unop(done, children, out, per_lane(f32::exp)),
+            NodeKind::Log(_) => unop(done, children, out, per_lane(safe_log)),
+            NodeKind::Pow(_) => binop(done, children, out, per_lane2(safe_pow)),
+            NodeKind::Min(_) => binop(done, children, out, |a, b| a.simd_min(b)),
+            NodeKind::Max(_) => binop(done, children, out, |a, b| a.simd_max(b)),
+            NodeKind::Atan2(_) => binop(done, children, out, per_lane2(f32::atan2)),
+            NodeKind::Floor(_) => unop(done, children, out, |v| v.floor()),
+            NodeKind::Fract(_) => unop(done, children, out, |v| v - v.floor()),
+            NodeKind::Mix(_) => ternop(done, children, out, |a, b, t| {
+                a * (Lanes::splat(1.) - t) + b * t
+            }),
+            NodeKind::Smoothstep(_) => ternop(done, children, out, |low, high, v| {
+                Lanes::from_array(std::array::from_fn(|i| {
+                    safe_smoothstep(low[i], high[i], v[i])
+                }))
+            }),
+            NodeKind::Clamp(_) => ternop(done, children, out, |v, low, high| {
+                v.simd_max(low).simd_min(high)
+            }),
+            NodeKind::Lt(_) => binop(done, children, out, per_lane2(|a, b| (a < b) as i32 as f32)),
+            // Both branches are already evaluated, `eval` only skips one
+            NodeKind::If(_) => {
+                let [condition, then, otherwise] = [0, 1, 2].map(|i| &done[children[i]]);
+                for (i, value) in out.iter_mut().enumerate() {
+                    *value = match condition[i] > 0. {
+                        true => then[i],
+                        false => otherwise[i],
+                    };
+                }
+            }
+            NodeKind::Radius => map(out, [xs, ys], |[x, y]| (x * x + y * y).sqrt()),
+            NodeKind::Angle => map(out, [xs, ys], |[x, y]| per_lane2(f32::atan2)(y, x)),
+            NodeKind::DistTo(px, py) => {
+                let (px, py) = (Lanes::splat(*px), Lanes::splat(*py));
+                map(out, [xs, ys], |[x, y]| {
+                    ((x - px) * (x - px) + (y - py) * (y - py)).sqrt()
+                })
+            }
+            NodeKind::T => out.fill(time),
+            NodeKind::SinT(freq) => out.fill((time * freq).sin()),
+            NodeKind::Saw => out.fill(saw(time)),
+        }
+    }
+
+    for (&root, out) in dag.roots.iter().zip(outs) {
+        out.copy_from_slice(&rows[root]);
+    }
+}
+
+fn unop(done: &[Vec<f32>], children: &[usize], out: &mut [f32], f: impl Fn(Lanes) -> Lanes) {
+    map(out, [&done[children[0]]], |[value]| f(value));
+}
+
+fn binop(
+    done: &[Vec<f32>],
+    children: &[usize],
+    out: &mut [f32],
+    f: impl Fn(Lanes, Lanes) -> Lanes,
+) {
+    let [lhs, rhs] = [0, 1].map(|i| done[children[i]].as_slice());
+    map(out, [lhs, rhs], |[lhs, rhs]| f(lhs, rhs));
+}
+
+fn ternop(
+    done: &[Vec<f32>],
+    children: &[usize],
+    out: &mut [f32],
+    f: impl Fn(Lanes, Lanes, Lanes) -> Lanes,
+) {
+    let [a, b, c] = [0, 1, 2].map(|i| done[children[i]].as_slice());
+    map(out, [a, b, c], |[a, b, c]| f(a, b, c));
+}
+
+/// Apply a scalar function to each lane
+fn per_lane(f: impl Fn(f32) -> f32) -> impl Fn(Lanes) -> Lanes {
+    move |v| Lanes::from_array(v.to_array().map(&f))
+}
+
+fn per_lane2(f: impl Fn(f32, f32) -> f32) -> impl Fn(Lanes, Lanes) -> Lanes {
+    move |a, b| Lanes::from_array(std::array::from_fn(|i| f(a[i], b[i])))
+}
+
+/// Fill `out` with `f` of the `inputs`, a chunk of lanes at a time
+fn map<const N: usize>(out: &mut [f32], inputs: [&[f32]; N], f: impl Fn([Lanes; N]) -> Lanes) {
+    for start in (0..out.len()).step_by(LANES) {
+        let end = (start + LANES).min(out.len());
+        // The last chunk is padded with zeros and only partially stored
+        let lanes = inputs.map(|input| {
+            let mut chunk = [0.; LANES];
+            chunk[..end - start].copy_from_slice(&input[start..end]);
+            Lanes::from_array(chunk)
+        });
+        out[start..end].copy_from_slice(&f(lanes).to_array()[..end - start]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        func_gen::tests::{assert_matches_eval, sample_trees},
+        optimize::merge_subtrees,
+    };
+
+    /// Not a multiple of `LANES`, so every row ends in a padded chunk
+    const ROW: usize = 13;
+
+    #[test]
+    fn evaluates_like_eval() {
+        let trees = sample_trees();
+        // Neighbouring trees share subtrees with the one repeated in the middle
+        for pair in trees.windows(2) {
+            let roots = [&pair[0], &pair[1], &pair[0]];
+            let dag = merge_subtrees(&roots);
+            for (channel, root) in roots.into_iter().enumerate() {
+                assert_matches_eval(root, |x, y, time| {
+                    // Every third pixel, in both chunks, is at `x`, the others
+                    // spread over the row so the `if`s take both branches
+                    let xs: Vec<f32> = (0..ROW)
+                        .map(|i| match i % 3 {
+                            0 => x,
+                            _ => i as f32 / 6. - 1.,
+                        })
+                        .collect();
+                    let mut outs = [[0.; ROW]; 3];
+                    let [r, g, b] = &mut outs;
+                    eval_batch(&xs, &[y; ROW], &dag, time, &mut Vec::new(), [r, g, b]);
+
+                    let row = outs[channel];
+                    let value = row[ROW - 1];
+                    for i in (0..ROW).step_by(3) {
+                        assert_eq!(row[i].to_bits(), value.to_bits(), "{:?}", root);
+                    }
+                    value
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+
+/// Samples taken along each axis of a pixel, both renderers average
+/// `samples²` points per pixel to smooth the edges of `gt`, `mod` and such
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Supersampling {
+    pub samples: u32,
+}
+
+impl Default for Supersampling {
+    fn default() -> Self {
+        Supersampling { samples: 1 }
+    }
+}
+
+impl Supersampling {
+    /// Offsets of the samples from the pixel's position along one axis, in
+    /// pixels. They're centred on it like the GPU taps, so a single sample
+    /// stays where it is without supersampling
+    pub fn offsets(&self) -> impl Iterator<Item = f32> {
+        let samples = self.samples.max(1);
+        (0..samples).map(move |i| (i as f32 + 0.5) / samples as f32 - 0.5)
+    }
+
+    /// WGSL of a fragment shader body averaging `color(mesh, t)` over
+    /// jittered taps spread across the pixel
+    pub fn shader_code(&self) -> String {
+        if self.samples <= 1 {
+            return "return vec4f(color(mesh, t), 1.0);".to_string();
+        }
+
+        format!(
+            "let samples = {}u;
+            let pixel = fwidth(mesh.uv);
+            var sum = vec3f(0.0);
+            for (var i = 0u; i < samples * samples; i++) {{
+                let cell = vec2f(f32(i % samples), f32(i / samples));
+                var tap = mesh;
+                tap.uv = mesh.uv + ((cell + jitter(mesh.position.xy, i)) / f32(samples) - 0.5) * pixel;
+                sum += saturate(color(tap, t));
+            }}
+            return vec4f(sum / f32(samples * samples), 1.0);",
+            self.samples
+        )
+    }
+}
+
+/// Random offset in a sample's cell, different for every pixel and sample
+pub const JITTER_FUNCTION: &str = "
+fn jitter(position: vec2f, i: u32) -> vec2f {
+    let seed = dot(position, vec2f(12.9898, 78.233)) + f32(i) * 0.618034;
+    return fract(sin(vec2f(seed, seed + 1.0)) * 43758.547);
+}
+";
+
+pub struct SupersamplingPlugin;
+
+impl Plugin for SupersamplingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Supersampling>().add_systems(
+            Update,
+            cycle_supersampling.run_if(input_just_pressed(KeyCode::KeyA)),
+        );
+    }
+}
+
+// 1, 2 then 4 samples per axis
+fn cycle_supersampling(mut supersampling: ResMut<Supersampling>) {
+    supersampling.samples = match supersampling.samples {
+        1 => 2,
+        2 => 4,
+        _ => 1,
+    };
+    info!("supersampling: {0}x{0}", supersampling.samples);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_are_centred() {
+        let offsets = |samples| Supersampling { samples }.offsets().collect::<Vec<_>>();
+        assert_eq!(offsets(0), [0.]);
+        assert_eq!(offsets(1), [0.]);
+        assert_eq!(offsets(2), [-0.25, 0.25]);
+        assert_eq!(offsets(4), [-0.375, -0.125, 0.125, 0.375]);
+    }
+}
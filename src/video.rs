@@ -0,0 +1,107 @@
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    animation::AnimationTime,
+    artwork::Artwork,
+    render::{generate_image, render_pixels, CpuBackend},
+    supersampling::Supersampling,
+};
+
+/// Raw RGBA frames `1 / fps` seconds apart, streamed to stdout or to an
+/// `ffmpeg` encoding them. The time step doesn't depend on how long frames
+/// take to render, so the same options always give the same video
+#[derive(Debug, Clone, Copy)]
+pub struct VideoExport {
+    pub duration: f32,
+    pub fps: u32,
+}
+
+impl VideoExport {
+    pub fn frames(&self) -> u32 {
+        (self.duration * self.fps as f32).round() as u32
+    }
+
+    /// Render the frames from `start` seconds on into `path`, `-` writes the
+    /// raw pixels to stdout and anything else is encoded by `ffmpeg`
+    pub fn stream(
+        &self,
+        artwork: &Artwork,
+        (width, height): (u32, u32),
+        supersampling: Supersampling,
+        start: f32,
+        path: &Path,
+    ) -> Result<(), String> {
+        if path == Path::new("-") {
+            let mut stdout = BufWriter::new(std::io::stdout().lock());
+            return self
+                .write_frames(&mut stdout, artwork, (width, height), supersampling, start)
+                .map_err(|error| format!("could not write frames to stdout: {}", error));
+        }
+
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-loglevel",
+                "error",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+            ])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &self.fps.to_string(), "-i", "-"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("could not start ffmpeg: {}", error))?;
+
+        // ffmpeg stopping early shows up as a broken pipe, its exit status
+        // below tells more
+        let written = ffmpeg.stdin.take().map(|stdin| {
+            self.write_frames(
+                &mut BufWriter::new(stdin),
+                artwork,
+                (width, height),
+                supersampling,
+                start,
+            )
+        });
+        let status = ffmpeg
+            .wait()
+            .map_err(|error| format!("ffmpeg failed: {}", error))?;
+        if !status.success() {
+            return Err(format!("ffmpeg failed with {}", status));
+        }
+        written
+            .unwrap_or(Ok(()))
+            .map_err(|error| format!("could not write frames to ffmpeg: {}", error))
+    }
+
+    fn write_frames(
+        &self,
+        writer: &mut impl Write,
+        artwork: &Artwork,
+        (width, height): (u32, u32),
+        supersampling: Supersampling,
+        start: f32,
+    ) -> std::io::Result<()> {
+        let animation = AnimationTime::default();
+        for i in 0..self.frames() {
+            let seconds = start + i as f32 / self.fps as f32;
+            let mut image = generate_image(width, height);
+            render_pixels(
+                &mut image,
+                artwork,
+                animation.at(seconds),
+                CpuBackend::default(),
+                supersampling,
+            );
+            writer.write_all(&image.data)?;
+        }
+        writer.flush()
+    }
+}